@@ -0,0 +1,201 @@
+use near_sdk::{AccountId, PromiseResult, Timestamp, env, log, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub type CampaignId = String;
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum CampaignStatus {
+    Active,
+    Succeeded,
+    Failed
+}
+
+/**
+ * Crowdfunded pre-order campaign of a merchant, all or nothing
+ * - Orders paid for the campaign are held until the deadline
+ * - If raised reaches goal the funds settle to the merchant, otherwise every order is refunded in batches
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Campaign {
+    pub campaign_id: CampaignId,
+    pub merchant_id: AccountId,
+    pub token: Option<AccountId>,
+    pub goal: U128,
+    pub deadline: Timestamp,
+    pub raised: U128,
+    pub status: CampaignStatus,
+    pub is_settled: bool,
+    // Index of the next order to refund when the campaign failed
+    pub refund_cursor: u64,
+    // Orders whose refund transfer failed, retried first by refund_campaign
+    pub failed_refunds: Vec<OrderId>
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    pub fn create_campaign(&mut self, campaign_id: CampaignId, token: Option<AccountId>, goal: U128, deadline: Timestamp) -> Campaign {
+        let merchant_id = self.assert_merchant();
+        assert!(self.campaigns.get(&campaign_id).is_none(), "ERROR_CAMPAIGN_ALREADY_EXISTS");
+        assert!(goal.0 > 0, "ERROR_INVALID_GOAL");
+        assert!(deadline > env::block_timestamp(), "ERROR_INVALID_DEADLINE");
+        self.payment_method_of(&token);
+
+        let campaign = Campaign {
+            campaign_id: campaign_id.clone(),
+            merchant_id,
+            token,
+            goal,
+            deadline,
+            raised: U128(0),
+            status: CampaignStatus::Active,
+            is_settled: false,
+            refund_cursor: 0,
+            failed_refunds: vec![]
+        };
+        self.campaigns.insert(&campaign_id, &campaign);
+        self.campaign_orders.insert(&campaign_id, &Vector::new(StorageKey::CampaignOrders {
+            campaign_hash: env::sha256_array(campaign_id.as_bytes())
+        }));
+        campaign
+    }
+
+    /*
+     * Close a campaign after its deadline, anyone can call it
+     * - Succeeded: transfer the raised funds to the merchant, calling it again retries a failed transfer
+     * - Failed: orders become refundable with refund_campaign
+     */
+    pub fn finalize_campaign(&mut self, campaign_id: CampaignId) -> PromiseOrValue<U128> {
+        let mut campaign = self.get_campaign(campaign_id.clone());
+        assert!(env::block_timestamp() > campaign.deadline, "ERROR_CAMPAIGN_NOT_ENDED");
+
+        if campaign.status == CampaignStatus::Active {
+            campaign.status = if campaign.raised.0 >= campaign.goal.0 {
+                CampaignStatus::Succeeded
+            } else {
+                CampaignStatus::Failed
+            };
+        }
+        if campaign.status == CampaignStatus::Failed || campaign.is_settled {
+            self.campaigns.insert(&campaign_id, &campaign);
+            return PromiseOrValue::Value(U128(0));
+        }
+
+        campaign.is_settled = true;
+        self.campaigns.insert(&campaign_id, &campaign);
+
+        let promise = self.internal_transfer(campaign.token, campaign.merchant_id, campaign.raised.0, "Settle campaign from payment contract")
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_attached_deposit(0)
+                    .with_static_gas(TRANSFER_GAS)
+                    .on_campaign_settled(campaign_id)
+            );
+        PromiseOrValue::Promise(promise)
+    }
+
+    /*
+     * Refund up to limit orders of a failed campaign, anyone can call it until every order is refunded
+     * - Orders whose refund failed are retried before the next orders
+     * - Not subject to multisig approval, each order amount only goes back to its payer
+     * - Return the number of orders left to refund
+     */
+    pub fn refund_campaign(&mut self, campaign_id: CampaignId, limit: u32) -> u64 {
+        let mut campaign = self.get_campaign(campaign_id.clone());
+        assert_eq!(campaign.status, CampaignStatus::Failed, "ERROR_CAMPAIGN_NOT_FAILED");
+        assert!(limit > 0, "ERROR_INVALID_LIMIT");
+
+        let orders = self.campaign_orders.get(&campaign_id).unwrap();
+        let retries = campaign.failed_refunds.len().min(limit as usize);
+        let mut order_ids: Vec<OrderId> = campaign.failed_refunds.drain(..retries).collect();
+        let end = orders.len().min(campaign.refund_cursor + (limit as usize - retries) as u64);
+        order_ids.extend((campaign.refund_cursor..end).map(|index| orders.get(index).unwrap()));
+        campaign.refund_cursor = end;
+        self.campaigns.insert(&campaign_id, &campaign);
+
+        for order_id in order_ids {
+            if !self.get_order(order_id.clone()).is_refund {
+                self.internal_refund(order_id);
+            }
+        }
+        orders.len() - end + campaign.failed_refunds.len() as u64
+    }
+
+    pub fn get_campaign(&self, campaign_id: CampaignId) -> Campaign {
+        self.campaigns.get(&campaign_id).expect("NOT_FOUND_CAMPAIGN")
+    }
+
+    pub fn get_campaign_orders(&self, campaign_id: CampaignId, from_index: u64, limit: u64) -> Vec<OrderId> {
+        let orders = self.campaign_orders.get(&campaign_id).expect("NOT_FOUND_CAMPAIGN");
+        (from_index..orders.len().min(from_index + limit))
+            .map(|index| orders.get(index).unwrap())
+            .collect()
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Add a payment to an active campaign, the order must be of the campaign merchant and token
+     */
+    pub(crate) fn internal_add_campaign_order(&mut self, campaign_id: &CampaignId, order: &Order) {
+        let mut campaign = self.get_campaign(campaign_id.clone());
+        assert!(
+            campaign.status == CampaignStatus::Active && env::block_timestamp() <= campaign.deadline,
+            "ERROR_CAMPAIGN_ENDED"
+        );
+        assert_eq!(campaign.merchant_id, order.merchant_id, "ERROR_CAMPAIGN_MERCHANT_MISMATCH");
+        assert_eq!(campaign.token, order.token_id, "ERROR_CAMPAIGN_TOKEN_MISMATCH");
+
+        campaign.raised = U128(campaign.raised.0 + order.amount);
+        self.campaigns.insert(campaign_id, &campaign);
+
+        let mut orders = self.campaign_orders.get(campaign_id).unwrap();
+        orders.push(&order.order_id);
+        self.campaign_orders.insert(campaign_id, &orders);
+    }
+
+    pub(crate) fn internal_on_campaign_settled(&mut self, campaign_id: CampaignId) -> U128 {
+        assert_eq!(env::promise_results_count(), 1, "ERROR_TOO_MANY_RESULTS");
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_value) => U128(0),
+            PromiseResult::Failed => {
+                // allow finalize_campaign to retry
+                let mut campaign = self.get_campaign(campaign_id.clone());
+                campaign.is_settled = false;
+                self.campaigns.insert(&campaign_id, &campaign);
+                log!("Failed to settle campaign {}", campaign_id);
+                campaign.raised
+            }
+        }
+    }
+
+    /*
+     * Queue a failed campaign order for refund_campaign to retry after its refund transfer failed
+     */
+    pub(crate) fn internal_on_campaign_refund_failed(&mut self, order: &Order) {
+        if let Some(campaign_id) = &order.campaign_id {
+            let mut campaign = self.get_campaign(campaign_id.clone());
+            if campaign.status == CampaignStatus::Failed {
+                campaign.failed_refunds.push(order.order_id.clone());
+                self.campaigns.insert(campaign_id, &campaign);
+            }
+        }
+    }
+
+    /*
+     * Campaign funds are held until the deadline, their orders are only refunded when the campaign failed
+     */
+    pub(crate) fn assert_campaign_refundable(&self, order: &Order) {
+        if let Some(campaign_id) = &order.campaign_id {
+            let campaign = self.get_campaign(campaign_id.clone());
+            assert_eq!(campaign.status, CampaignStatus::Failed, "ERROR_CAMPAIGN_ORDER_NOT_REFUNDABLE");
+        }
+    }
+}
+
@@ -0,0 +1,126 @@
+use ed25519_dalek::Verifier;
+use near_sdk::{AccountId, Duration, IntoStorageKey, Timestamp, env};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{Base64VecU8, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+// 1 day, nonces are remembered until their signed order expires
+pub const MAX_SIGNED_ORDER_TTL: Duration = 24 * 60 * 60 * 1_000_000_000;
+const MAX_NONCES_PRUNED_PER_CALL: u64 = 10;
+
+/**
+ * Order details signed by the merchant backend, so buyers cannot choose their own price
+ * - The signature covers the borsh serialization of the payload
+ * - contract_id: the payment contract the order is signed for, so it can't be replayed on another deployment
+ * - expires_at: at most MAX_SIGNED_ORDER_TTL after the payment
+ * - token = None means the order must be paid with NEAR
+ * - line_items (refunded_quantity = 0) must add up to amount, or be empty
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CheckoutPayload {
+    pub contract_id: AccountId,
+    pub order_id: OrderId,
+    pub merchant_id: AccountId,
+    pub payer_id: AccountId,
+    pub token: Option<AccountId>,
+    pub amount: U128,
+    pub expires_at: U64,
+    pub nonce: U64,
+    pub line_items: Vec<LineItem>
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignedOrder {
+    pub payload: CheckoutPayload,
+    pub signature: Base64VecU8
+}
+
+/**
+ * Nonces of the signed orders not expired yet per merchant, in the order they were used
+ * - Expired nonces are pruned as new ones are used, their signed orders can't be paid anymore anyway
+ */
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct UsedNonces {
+    expirations: LookupMap<(AccountId, u64), Timestamp>,
+    queue: LookupMap<u64, (AccountId, u64)>,
+    head: u64,
+    tail: u64
+}
+
+impl UsedNonces {
+    pub fn new<S: IntoStorageKey>(expirations_prefix: S, queue_prefix: S) -> Self {
+        Self {
+            expirations: LookupMap::new(expirations_prefix),
+            queue: LookupMap::new(queue_prefix),
+            head: 0,
+            tail: 0
+        }
+    }
+
+    /*
+     * Remember a nonce until expires_at, false when it is already used
+     */
+    pub fn insert(&mut self, nonce_key: &(AccountId, u64), expires_at: Timestamp) -> bool {
+        self.prune();
+        if self.expirations.insert(nonce_key, &expires_at).is_some() {
+            return false;
+        }
+        self.queue.insert(&self.tail, nonce_key);
+        self.tail += 1;
+        true
+    }
+
+    pub fn contains(&self, nonce_key: &(AccountId, u64)) -> bool {
+        self.expirations.contains_key(nonce_key)
+    }
+
+    fn prune(&mut self) {
+        let now = env::block_timestamp();
+        let end = self.tail.min(self.head + MAX_NONCES_PRUNED_PER_CALL);
+        while self.head < end {
+            let nonce_key = self.queue.get(&self.head).unwrap();
+            if self.expirations.get(&nonce_key).is_some_and(|expires_at| expires_at >= now) {
+                break;
+            }
+            self.expirations.remove(&nonce_key);
+            self.queue.remove(&self.head);
+            self.head += 1;
+        }
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Verify a signed checkout order against the payment and consume its nonce
+     * - Return the amount to charge
+     */
+    pub(crate) fn internal_verify_signed_order(&mut self, payment: &Payment, signed_order: &SignedOrder) -> Balance {
+        let payload = &signed_order.payload;
+        assert_eq!(payload.contract_id, env::current_account_id(), "ERROR_SIGNED_ORDER_MISMATCH");
+        assert_eq!(payload.order_id, payment.order_id, "ERROR_SIGNED_ORDER_MISMATCH");
+        assert_eq!(payload.payer_id, payment.payer_id, "ERROR_SIGNED_ORDER_MISMATCH");
+        assert_eq!(payload.token, payment.token_id, "ERROR_SIGNED_ORDER_MISMATCH");
+        assert!(env::block_timestamp() <= payload.expires_at.0, "ERROR_SIGNED_ORDER_EXPIRED");
+        assert!(payload.expires_at.0 <= env::block_timestamp() + MAX_SIGNED_ORDER_TTL, "ERROR_SIGNED_ORDER_TTL_TOO_LONG");
+
+        let merchant = self.get_merchant(payload.merchant_id.clone());
+        let public_key = merchant.public_key.expect("ERROR_MERCHANT_KEY_NOT_FOUND");
+        // Skip the curve type prefix of near_sdk::PublicKey
+        let public_key = ed25519_dalek::PublicKey::from_bytes(&public_key.as_bytes()[1..])
+            .expect("ERROR_INVALID_MERCHANT_KEY");
+        let signature = ed25519_dalek::Signature::try_from(signed_order.signature.0.as_slice())
+            .expect("ERROR_INVALID_SIGNATURE");
+        let message = payload.try_to_vec().unwrap();
+        assert!(public_key.verify(&message, &signature).is_ok(), "ERROR_INVALID_SIGNATURE");
+
+        let nonce_key = (payload.merchant_id.clone(), payload.nonce.0);
+        assert!(self.used_nonces.insert(&nonce_key, payload.expires_at.0), "ERROR_NONCE_ALREADY_USED");
+
+        payload.amount.0
+    }
+}
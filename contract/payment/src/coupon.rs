@@ -0,0 +1,143 @@
+use near_sdk::{AccountId, Balance, Timestamp, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub type CouponKey = (AccountId, String);
+
+const MAX_BASIS_POINTS: u16 = 10_000;
+
+/**
+ * Discount of a coupon
+ * - Percentage: basis points of the order amount (10000 = 100%)
+ * - Fixed: amount in the smallest unit of the only allowed token
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Discount {
+    Percentage { basis_points: u16 },
+    Fixed { amount: U128 }
+}
+
+/**
+ * Coupon of a merchant, redeemed by code on the merchant's orders
+ * - usage_limit / per_account_limit: None means unlimited
+ * - allowed_tokens: tokens the order can be paid with (None for NEAR), empty means any
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Coupon {
+    pub merchant_id: AccountId,
+    pub code: String,
+    pub discount: Discount,
+    pub usage_limit: Option<u32>,
+    pub per_account_limit: Option<u32>,
+    pub starts_at: Option<Timestamp>,
+    pub ends_at: Option<Timestamp>,
+    pub allowed_tokens: Vec<Option<AccountId>>,
+    #[serde(default)]
+    pub used_count: u32
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Create or replace a coupon of the calling merchant, keeping its usage count
+     */
+    pub fn set_coupon(&mut self, coupon: Coupon) -> Coupon {
+        let mut coupon = coupon;
+        coupon.merchant_id = self.assert_merchant();
+        match &coupon.discount {
+            Discount::Percentage { basis_points } => {
+                assert!(*basis_points > 0 && *basis_points <= MAX_BASIS_POINTS, "ERROR_INVALID_DISCOUNT");
+            }
+            Discount::Fixed { amount } => {
+                assert!(amount.0 > 0, "ERROR_INVALID_DISCOUNT");
+                assert_eq!(coupon.allowed_tokens.len(), 1, "ERROR_FIXED_DISCOUNT_NEEDS_ONE_TOKEN");
+            }
+        }
+        for token in coupon.allowed_tokens.iter() {
+            self.payment_method_of(token);
+        }
+
+        let key = (coupon.merchant_id.clone(), coupon.code.clone());
+        coupon.used_count = self.coupons.get(&key).map_or(0, |existing| existing.used_count);
+        self.coupons.insert(&key, &coupon);
+        coupon
+    }
+
+    pub fn remove_coupon(&mut self, code: String) {
+        let merchant_id = self.assert_merchant();
+        self.coupons.remove(&(merchant_id, code)).expect("NOT_FOUND_COUPON");
+    }
+
+    pub fn get_coupon(&self, merchant_id: AccountId, code: String) -> Coupon {
+        self.coupons.get(&(merchant_id, code)).expect("NOT_FOUND_COUPON")
+    }
+
+    pub fn get_coupon_usage(&self, merchant_id: AccountId, code: String, account_id: AccountId) -> u32 {
+        self.coupon_usages.get(&(merchant_id, code, account_id)).unwrap_or(0)
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Redeem a coupon for an order of the merchant and return the discount
+     */
+    pub(crate) fn internal_redeem_coupon(
+        &mut self,
+        merchant_id: &AccountId,
+        code: &str,
+        payment: &Payment,
+        amount: Balance
+    ) -> Balance {
+        let key = (merchant_id.clone(), code.to_owned());
+        let mut coupon = self.coupons.get(&key).expect("NOT_FOUND_COUPON");
+
+        let now = env::block_timestamp();
+        assert!(coupon.starts_at.map_or(true, |starts_at| now >= starts_at), "ERROR_COUPON_NOT_STARTED");
+        assert!(coupon.ends_at.map_or(true, |ends_at| now <= ends_at), "ERROR_COUPON_ENDED");
+        assert!(
+            coupon.allowed_tokens.is_empty() || coupon.allowed_tokens.contains(&payment.token_id),
+            "ERROR_COUPON_TOKEN_NOT_ALLOWED"
+        );
+        assert!(coupon.usage_limit.map_or(true, |limit| coupon.used_count < limit), "ERROR_COUPON_USAGE_LIMIT");
+
+        let usage_key = (merchant_id.clone(), code.to_owned(), payment.payer_id.clone());
+        let usage = self.coupon_usages.get(&usage_key).unwrap_or(0);
+        assert!(coupon.per_account_limit.map_or(true, |limit| usage < limit), "ERROR_COUPON_ACCOUNT_LIMIT");
+
+        coupon.used_count += 1;
+        self.coupons.insert(&key, &coupon);
+        self.coupon_usages.insert(&usage_key, &(usage + 1));
+
+        match coupon.discount {
+            Discount::Percentage { basis_points } => {
+                (U256::from(amount) * U256::from(basis_points) / U256::from(MAX_BASIS_POINTS)).as_u128()
+            }
+            Discount::Fixed { amount: discount } => discount.0.min(amount)
+        }
+    }
+
+    /*
+     * Give back the coupon usage of a fully refunded order
+     */
+    pub(crate) fn internal_restore_coupon(&mut self, order: &Order) {
+        let code = match &order.coupon_code {
+            Some(code) => code,
+            None => return
+        };
+
+        let key = (order.merchant_id.clone(), code.clone());
+        if let Some(mut coupon) = self.coupons.get(&key) {
+            coupon.used_count = coupon.used_count.saturating_sub(1);
+            self.coupons.insert(&key, &coupon);
+        }
+
+        let usage_key = (order.merchant_id.clone(), code.clone(), order.payer_id.clone());
+        if let Some(usage) = self.coupon_usages.get(&usage_key) {
+            self.coupon_usages.insert(&usage_key, &usage.saturating_sub(1));
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use near_sdk::{AccountId, PromiseOrValue, Promise, env, serde_json, ext_contract, near_bindgen};
 use near_sdk::serde::{Serialize, Deserialize};
-use crate::{Order, PaymentMethod, OrderId, U128, EcommerceContract, EcommerceContractExt};
+use crate::{CampaignId, GiftCardUse, LineItem, Payment, PaymentMethod, SignedOrder, OrderId, U128, EcommerceContract, EcommerceContractExt};
 
 
 pub trait FungibleTokenReceiver {
@@ -14,46 +14,70 @@ pub trait FungibleToken {
 
 #[derive(Deserialize, Serialize)]
 #[serde(crate="near_sdk::serde")]
-struct FTMessage {
-    order_id: OrderId,
-    order_amount: U128,
+pub struct FTMessage {
+    pub order_id: OrderId,
+    pub order_amount: U128,
+    pub quote_id: Option<String>,
+    pub max_amount: Option<U128>,
+    pub signed_order: Option<SignedOrder>,
+    pub line_items: Option<Vec<LineItem>>,
+    pub coupon_code: Option<String>,
+    pub gift_card: Option<GiftCardUse>,
+    pub campaign_id: Option<CampaignId>,
+    pub tip: Option<U128>,
+    pub referrer: Option<AccountId>,
+}
+
+/**
+ * Batch checkout message, pays each (order_id, order_amount) from the transferred amount
+ */
+#[derive(Deserialize, Serialize)]
+#[serde(crate="near_sdk::serde")]
+pub struct FTBatchMessage {
+    pub orders: Vec<(OrderId, U128)>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate="near_sdk::serde", untagged)]
+pub enum FTPayload {
+    Single(Box<FTMessage>),
+    Batch(FTBatchMessage),
 }
 
 #[near_bindgen]
 impl FungibleTokenReceiver for EcommerceContract {
     fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
-        assert_eq!(env::predecessor_account_id(), self.ft_contract_id);
-        let FTMessage {order_id, order_amount} = serde_json::from_str(&msg).expect("ERROR_NOT_VALID_MESSAGE");
-
-        assert!(amount.0 >= order_amount.0);
-
-        // check order status: completed or not completed
-        match self.orders.get(&order_id) {
-            Some(order) => {
-                assert!(!order.is_completed)
-            },
-            None => {}
-        }
-
-        // save order information of user
-        let order: Order = Order {
-            order_id: order_id.clone(),
+        let token_id = env::predecessor_account_id();
+        assert!(self.accepted_tokens.get(&token_id).is_some(), "ERROR_TOKEN_NOT_ACCEPTED");
+        let message = match serde_json::from_str(&msg).expect("ERROR_NOT_VALID_MESSAGE") {
+            FTPayload::Single(message) => *message,
+            FTPayload::Batch(FTBatchMessage {orders}) => {
+                let change = self.internal_pay_orders(sender_id, PaymentMethod::FungibleToken, Some(token_id), orders, amount.0);
+                return PromiseOrValue::Value(U128(change));
+            }
+        };
+        let FTMessage {order_id, order_amount, quote_id, max_amount, signed_order, line_items, coupon_code, gift_card, campaign_id, tip, referrer} = message;
+
+        let change = self.internal_pay_order(Payment {
+            order_id,
             payer_id: sender_id,
             payment_method: PaymentMethod::FungibleToken,
-            amount: order_amount.0,
+            token_id: Some(token_id),
+            order_amount: order_amount.0,
             received_amount: amount.0,
-            is_completed: true,
-            is_refund: false,
-            created_at: env::block_timestamp(),
-        };
-
-        self.orders.insert(&order_id, &order);
+            quote_id,
+            max_amount: max_amount.map(|amount| amount.0),
+            signed_order,
+            line_items,
+            coupon_code,
+            gift_card,
+            campaign_id,
+            tip: tip.map_or(0, |tip| tip.0),
+            nft_payment: None,
+            referrer
+        });
 
         // return balance to user
-        if amount.0 > order_amount.0 {
-            PromiseOrValue::Value(U128(amount.0 - order_amount.0))
-        } else {
-            PromiseOrValue::Value(U128(0))
-        }
+        PromiseOrValue::Value(U128(change))
     }
 }
\ No newline at end of file
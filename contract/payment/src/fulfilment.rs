@@ -0,0 +1,117 @@
+use near_sdk::{Timestamp, env, near_bindgen, serde_json};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum FulfilmentStatus {
+    Processing,
+    Shipped,
+    Delivered,
+    Returned
+}
+
+/**
+ * Fulfilment step of an order
+ * - tracking_hash: hash of the carrier tracking number, required when shipped
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FulfilmentUpdate {
+    pub status: FulfilmentStatus,
+    pub tracking_hash: Option<Base58CryptoHash>,
+    pub updated_at: Timestamp
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Fulfilment {
+    pub status: Option<FulfilmentStatus>,
+    pub updates: Vec<FulfilmentUpdate>,
+    pub delivery_confirmed_at: Option<Timestamp>
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Move a paid order of the calling merchant to the next fulfilment status
+     * - Processing -> Shipped -> Delivered, Shipped or Delivered -> Returned
+     */
+    pub fn update_fulfilment(&mut self, order_id: OrderId, status: FulfilmentStatus, tracking_hash: Option<Base58CryptoHash>) {
+        let mut order = self.get_order(order_id.clone());
+        assert_eq!(env::predecessor_account_id(), order.merchant_id, "ERROR_NOT_ALLOWED");
+        assert!(order.is_completed && !order.is_refund, "ERROR_ORDER_NOT_FULFILLABLE");
+        assert!(status != FulfilmentStatus::Shipped || tracking_hash.is_some(), "ERROR_TRACKING_HASH_REQUIRED");
+
+        let current_status = order.fulfilment.last().map(|update| update.status);
+        let is_allowed = matches!(
+            (current_status, status),
+            (None, FulfilmentStatus::Processing)
+                | (None | Some(FulfilmentStatus::Processing), FulfilmentStatus::Shipped)
+                | (Some(FulfilmentStatus::Shipped), FulfilmentStatus::Delivered)
+                | (Some(FulfilmentStatus::Shipped | FulfilmentStatus::Delivered), FulfilmentStatus::Returned)
+        );
+        assert!(is_allowed, "ERROR_INVALID_FULFILMENT_STATUS");
+
+        add_fulfilment_update(&mut order, status, tracking_hash);
+        self.orders.insert(&order_id, &order);
+    }
+
+    /*
+     * Confirm delivery as the payer, the order funds held for the merchant become available right away
+     * - An NFT paid for the order is released to the merchant
+     */
+    pub fn confirm_delivery(&mut self, order_id: OrderId) {
+        let mut order = self.get_order(order_id.clone());
+        assert_eq!(env::predecessor_account_id(), order.payer_id, "ERROR_NOT_ALLOWED");
+        assert!(order.is_completed && !order.is_refund, "ERROR_ORDER_NOT_FULFILLABLE");
+        assert!(order.delivery_confirmed_at.is_none(), "ERROR_DELIVERY_ALREADY_CONFIRMED");
+
+        let current_status = order.fulfilment.last().map(|update| update.status);
+        assert!(
+            matches!(current_status, Some(FulfilmentStatus::Shipped | FulfilmentStatus::Delivered)),
+            "ERROR_ORDER_NOT_SHIPPED"
+        );
+        if current_status == Some(FulfilmentStatus::Shipped) {
+            add_fulfilment_update(&mut order, FulfilmentStatus::Delivered, None);
+        }
+        order.delivery_confirmed_at = Some(env::block_timestamp());
+        self.orders.insert(&order_id, &order);
+
+        self.internal_release_order_funds(&order);
+        if order.nft_payment.is_some() {
+            self.internal_settle_nft_payment(order);
+        }
+    }
+
+    pub fn get_fulfilment(&self, order_id: OrderId) -> Fulfilment {
+        let order = self.get_order(order_id);
+        Fulfilment {
+            status: order.fulfilment.last().map(|update| update.status),
+            updates: order.fulfilment,
+            delivery_confirmed_at: order.delivery_confirmed_at
+        }
+    }
+}
+
+/*
+ * Record a fulfilment step and log it as a NEP-297 event for merchant and buyer apps
+ */
+pub(crate) fn add_fulfilment_update(order: &mut Order, status: FulfilmentStatus, tracking_hash: Option<Base58CryptoHash>) {
+    let update = FulfilmentUpdate { status, tracking_hash, updated_at: env::block_timestamp() };
+    env::log_str(&format!("EVENT_JSON:{}", serde_json::json!({
+        "standard": "ecommerce_payment",
+        "version": "1.0.0",
+        "event": "fulfilment_update",
+        "data": [{
+            "order_id": order.order_id,
+            "merchant_id": order.merchant_id,
+            "payer_id": order.payer_id,
+            "update": update
+        }]
+    })));
+    order.fulfilment.push(update);
+}
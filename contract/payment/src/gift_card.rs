@@ -0,0 +1,176 @@
+use near_sdk::{AccountId, Balance, CryptoHash, Timestamp, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/**
+ * Gift card of a merchant, identified by the sha256 hash of its code
+ * - Only the holder can spend the card, the code is never sent on-chain so it can be shared without risk
+ * - balance is in the smallest unit of token (None for NEAR) and only spendable on the merchant's orders
+ * - Refunds of orders paid with the card go back to its balance as store credit
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GiftCard {
+    pub code_hash: Base58CryptoHash,
+    pub merchant_id: AccountId,
+    pub holder_id: AccountId,
+    pub token: Option<AccountId>,
+    pub initial_balance: U128,
+    pub balance: U128,
+    // Order that bought the card, None when issued by the merchant
+    pub order_id: Option<OrderId>,
+    pub created_at: Timestamp
+}
+
+/**
+ * Gift card use of a payment
+ * - Purchase: the order buys a new card worth the paid amount, held by holder_id or by the payer when not set
+ * - Redeem: the card balance pays the order first, the rest is paid with the attached tokens, the payer must hold the card
+ */
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum GiftCardUse {
+    Purchase { code_hash: Base58CryptoHash, holder_id: Option<AccountId> },
+    Redeem { code_hash: Base58CryptoHash }
+}
+
+/**
+ * Part of an order paid with a gift card and the part credited back to the card
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GiftCardPayment {
+    pub code_hash: Base58CryptoHash,
+    pub amount: U128,
+    pub refunded_amount: U128
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Issue a gift card of the calling merchant without payment, e.g. for promotions
+     */
+    pub fn issue_gift_card(&mut self, code_hash: Base58CryptoHash, holder_id: AccountId, token: Option<AccountId>, amount: U128) -> GiftCard {
+        let merchant_id = self.assert_merchant();
+        self.payment_method_of(&token);
+        self.internal_issue_gift_card(code_hash, merchant_id, holder_id, token, amount.0, None)
+    }
+
+    /*
+     * Give the card with its remaining balance to another account, only the holder can transfer it
+     */
+    pub fn transfer_gift_card(&mut self, code_hash: Base58CryptoHash, receiver_id: AccountId) -> GiftCard {
+        let key: CryptoHash = code_hash.into();
+        let mut gift_card = self.gift_cards.get(&key).expect("NOT_FOUND_GIFT_CARD");
+        assert_eq!(gift_card.holder_id, env::predecessor_account_id(), "ERROR_NOT_GIFT_CARD_HOLDER");
+
+        gift_card.holder_id = receiver_id;
+        self.gift_cards.insert(&key, &gift_card);
+        gift_card
+    }
+
+    pub fn get_gift_card(&self, code_hash: Base58CryptoHash) -> GiftCard {
+        self.gift_cards.get(&code_hash.into()).expect("NOT_FOUND_GIFT_CARD")
+    }
+
+    pub fn get_gift_card_balance(&self, code_hash: Base58CryptoHash) -> U128 {
+        self.get_gift_card(code_hash).balance
+    }
+}
+
+impl EcommerceContract {
+    pub(crate) fn internal_issue_gift_card(
+        &mut self,
+        code_hash: Base58CryptoHash,
+        merchant_id: AccountId,
+        holder_id: AccountId,
+        token: Option<AccountId>,
+        amount: Balance,
+        order_id: Option<OrderId>
+    ) -> GiftCard {
+        assert!(amount > 0, "ERROR_INVALID_AMOUNT");
+        let key: CryptoHash = code_hash.into();
+        assert!(self.gift_cards.get(&key).is_none(), "ERROR_GIFT_CARD_ALREADY_EXISTS");
+
+        let gift_card = GiftCard {
+            code_hash,
+            merchant_id,
+            holder_id,
+            token,
+            initial_balance: U128(amount),
+            balance: U128(amount),
+            order_id,
+            created_at: env::block_timestamp()
+        };
+        self.gift_cards.insert(&key, &gift_card);
+        gift_card
+    }
+
+    /*
+     * Spend up to amount from the gift card, the card must be held by the payer, of the order's merchant and the paid token
+     */
+    pub(crate) fn internal_redeem_gift_card(
+        &mut self,
+        code_hash: Base58CryptoHash,
+        payer_id: &AccountId,
+        merchant_id: &AccountId,
+        token: &Option<AccountId>,
+        amount: Balance
+    ) -> GiftCardPayment {
+        let key: CryptoHash = code_hash.into();
+        let mut gift_card = self.gift_cards.get(&key).expect("NOT_FOUND_GIFT_CARD");
+        assert_eq!(&gift_card.holder_id, payer_id, "ERROR_NOT_GIFT_CARD_HOLDER");
+        assert_eq!(&gift_card.merchant_id, merchant_id, "ERROR_GIFT_CARD_MERCHANT_MISMATCH");
+        assert_eq!(&gift_card.token, token, "ERROR_GIFT_CARD_TOKEN_MISMATCH");
+        assert!(gift_card.balance.0 > 0, "ERROR_GIFT_CARD_EMPTY");
+
+        let spent = gift_card.balance.0.min(amount);
+        gift_card.balance = U128(gift_card.balance.0 - spent);
+        self.gift_cards.insert(&key, &gift_card);
+
+        GiftCardPayment {
+            code_hash,
+            amount: U128(spent),
+            refunded_amount: U128(0)
+        }
+    }
+
+    /*
+     * Credit the card share of the refunded part of an order back to the card
+     * - Full refunds give back the whole card payment, line item refunds the share of the refunded lines
+     * - Only the difference with what was credited before is added, so retried refunds are not credited twice
+     */
+    pub(crate) fn internal_refund_gift_card(&mut self, order: &mut Order) {
+        let gift_card_payment = match &mut order.gift_card_payment {
+            Some(gift_card_payment) => gift_card_payment,
+            None => return
+        };
+
+        let refunded_amount = if order.is_refund {
+            gift_card_payment.amount.0
+        } else {
+            let items_total: Balance = order.line_items.iter().map(|line| line.total()).sum();
+            let refunded_total = order.line_items.iter()
+                .map(|line| U256::from(line.total()) * U256::from(line.refunded_quantity) / U256::from(line.quantity))
+                .fold(U256::zero(), |total, line_total| total + line_total);
+            if items_total == 0 {
+                return;
+            }
+            (refunded_total * U256::from(gift_card_payment.amount.0) / U256::from(items_total)).as_u128()
+        };
+
+        let credit = refunded_amount.saturating_sub(gift_card_payment.refunded_amount.0);
+        if credit == 0 {
+            return;
+        }
+        gift_card_payment.refunded_amount = U128(refunded_amount);
+
+        let key: CryptoHash = gift_card_payment.code_hash.into();
+        let mut gift_card = self.gift_cards.get(&key).expect("NOT_FOUND_GIFT_CARD");
+        gift_card.balance = U128(gift_card.balance.0 + credit);
+        self.gift_cards.insert(&key, &gift_card);
+    }
+}
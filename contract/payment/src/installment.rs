@@ -0,0 +1,267 @@
+use near_sdk::{AccountId, Balance, Duration, Timestamp, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PlanStatus {
+    // Approved by the merchant, waiting for the down payment
+    Pending,
+    Active,
+    Completed,
+    Defaulted
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ScheduleItem {
+    pub due_at: Timestamp,
+    pub amount: U128
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Installment {
+    pub due_at: Timestamp,
+    pub amount: U128,
+    pub late_fee: U128,
+    pub paid_at: Option<Timestamp>
+}
+
+/**
+ * Fee added to an installment paid more than grace_period after its due date
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LateFeeRule {
+    pub grace_period: Duration,
+    pub fee: U128
+}
+
+/**
+ * Buy-now-pay-later plan approved by the merchant for one payer
+ * - pay_order on the plan order pays the down payment first, then the installments in order
+ * - The order is created by the down payment and only completed once every installment is paid
+ * - The plan defaults when the next installment is still unpaid default_after its due date
+ * - A default closes the order at the amount collected so far, the merchant keeps it like any paid order
+ *   and settles with the payer through refund or a return
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InstallmentPlan {
+    pub order_id: OrderId,
+    pub merchant_id: AccountId,
+    pub payer_id: AccountId,
+    pub token: Option<AccountId>,
+    pub down_payment: U128,
+    pub installments: Vec<Installment>,
+    pub late_fee: LateFeeRule,
+    pub default_after: Duration,
+    pub status: PlanStatus,
+    pub created_at: Timestamp
+}
+
+impl InstallmentPlan {
+    pub fn next_installment(&self) -> Option<usize> {
+        self.installments.iter().position(|installment| installment.paid_at.is_none())
+    }
+
+    /*
+     * Status at the current block, a missed installment defaults the plan even before check_installment_plan
+     */
+    pub fn current_status(&self) -> PlanStatus {
+        match (self.status, self.next_installment()) {
+            (PlanStatus::Pending | PlanStatus::Active, Some(index)) => {
+                let due_at = self.installments[index].due_at;
+                if env::block_timestamp() > due_at + self.default_after {
+                    PlanStatus::Defaulted
+                } else {
+                    self.status
+                }
+            }
+            (status, _) => status
+        }
+    }
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_installment_plan(
+        &mut self,
+        order_id: OrderId,
+        payer_id: AccountId,
+        token: Option<AccountId>,
+        down_payment: U128,
+        schedule: Vec<ScheduleItem>,
+        late_fee: LateFeeRule,
+        default_after: Duration
+    ) -> InstallmentPlan {
+        let merchant_id = self.assert_merchant();
+        assert!(self.orders.get(&order_id).is_none(), "ERROR_ORDER_ALREADY_EXISTS");
+        assert!(self.invoices.get(&order_id).is_none(), "ERROR_ORDER_ALREADY_EXISTS");
+        assert!(self.installment_plans.get(&order_id).is_none(), "ERROR_ORDER_ALREADY_EXISTS");
+        assert!(!schedule.is_empty(), "ERROR_INVALID_SCHEDULE");
+        self.payment_method_of(&token);
+
+        let mut due_after = env::block_timestamp();
+        let installments = schedule.into_iter().map(|item| {
+            assert!(item.due_at > due_after, "ERROR_INVALID_SCHEDULE");
+            assert!(item.amount.0 > 0, "ERROR_INVALID_AMOUNT");
+            due_after = item.due_at;
+            Installment { due_at: item.due_at, amount: item.amount, late_fee: U128(0), paid_at: None }
+        }).collect();
+
+        let plan = InstallmentPlan {
+            order_id: order_id.clone(),
+            merchant_id,
+            payer_id,
+            token,
+            down_payment,
+            installments,
+            late_fee,
+            default_after,
+            status: PlanStatus::Pending,
+            created_at: env::block_timestamp()
+        };
+        self.installment_plans.insert(&order_id, &plan);
+        plan
+    }
+
+    /*
+     * Withdraw a plan before its down payment
+     */
+    pub fn cancel_installment_plan(&mut self, order_id: OrderId) {
+        let plan = self.get_installment_plan(order_id.clone());
+        assert_eq!(env::predecessor_account_id(), plan.merchant_id, "ERROR_NOT_ALLOWED");
+        assert_eq!(plan.status, PlanStatus::Pending, "ERROR_PLAN_ALREADY_STARTED");
+        self.installment_plans.remove(&order_id);
+    }
+
+    /*
+     * Save the default of a plan with a missed installment, anyone can call it
+     * - The order of the collected payments is completed, so the merchant can refund it
+     */
+    pub fn check_installment_plan(&mut self, order_id: OrderId) -> PlanStatus {
+        let mut plan = self.installment_plans.get(&order_id).expect("NOT_FOUND_INSTALLMENT_PLAN");
+        if plan.status != PlanStatus::Defaulted && plan.current_status() == PlanStatus::Defaulted {
+            if let Some(mut order) = self.orders.get(&order_id) {
+                order.is_completed = true;
+                self.orders.insert(&order_id, &order);
+            }
+            plan.status = PlanStatus::Defaulted;
+            self.installment_plans.insert(&order_id, &plan);
+        }
+        plan.status
+    }
+
+    pub fn get_installment_plan(&self, order_id: OrderId) -> InstallmentPlan {
+        let mut plan = self.installment_plans.get(&order_id).expect("NOT_FOUND_INSTALLMENT_PLAN");
+        plan.status = plan.current_status();
+        plan
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Pay the down payment or the next installment of a plan
+     * - Return the change to give back to the payer
+     */
+    pub(crate) fn internal_pay_installment(&mut self, payment: Payment) -> Balance {
+        let mut plan = self.installment_plans.get(&payment.order_id).unwrap();
+        assert_eq!(payment.payer_id, plan.payer_id, "ERROR_NOT_PLAN_PAYER");
+        assert_eq!(payment.token_id, plan.token, "ERROR_PLAN_TOKEN_MISMATCH");
+        assert!(
+            payment.signed_order.is_none() && payment.line_items.is_none() && payment.coupon_code.is_none()
+                && payment.gift_card.is_none() && payment.campaign_id.is_none() && payment.tip == 0
+                && payment.referrer.is_none(),
+            "ERROR_NOT_ALLOWED_FOR_INSTALLMENTS"
+        );
+
+        let now = env::block_timestamp();
+        let status = plan.current_status();
+        assert_ne!(status, PlanStatus::Completed, "ERROR_ORDER_ALREADY_PAID");
+        assert_ne!(status, PlanStatus::Defaulted, "ERROR_PLAN_DEFAULTED");
+
+        let is_new_order = status == PlanStatus::Pending;
+        let amount = if is_new_order {
+            plan.down_payment.0
+        } else {
+            let index = plan.next_installment().unwrap();
+            let installment = &mut plan.installments[index];
+            if now > installment.due_at + plan.late_fee.grace_period {
+                installment.late_fee = plan.late_fee.fee;
+            }
+            installment.amount.0 + installment.late_fee.0
+        };
+        assert!(payment.received_amount >= amount, "ERROR_DEPOSIT_NOT_ENOUGH");
+        // like other payments, before anything is recorded
+        self.internal_check_risk(&plan.payer_id, &plan.token, amount, is_new_order);
+
+        if is_new_order {
+            let tax_jurisdiction = self.get_merchant(plan.merchant_id.clone()).jurisdiction;
+            let tax = tax_jurisdiction.as_ref()
+                .map_or(0, |jurisdiction| self.internal_compute_tax(jurisdiction, &plan.merchant_id, &[], amount));
+            self.orders.insert(&plan.order_id, &Order {
+                order_id: plan.order_id.clone(),
+                merchant_id: plan.merchant_id.clone(),
+                payer_id: payment.payer_id,
+                payment_method: payment.payment_method,
+                token_id: payment.token_id,
+                amount,
+                received_amount: payment.received_amount,
+                is_completed: false,
+                is_refund: false,
+                created_at: now,
+                fiat_amount: None,
+                rate: None,
+                line_items: vec![],
+                refunded_amount: 0,
+                coupon_code: None,
+                discount: 0,
+                reward_token_id: None,
+                reward_amount: 0,
+                gift_card_payment: None,
+                issued_gift_card: None,
+                campaign_id: None,
+                tip: 0,
+                nft_payment: None,
+                tax,
+                tax_jurisdiction,
+                fulfilment: vec![],
+                delivery_confirmed_at: None,
+                referrer_id: None,
+                commission: 0,
+                price_source: PriceSource::InstallmentPlan
+            });
+            plan.status = PlanStatus::Active;
+        } else {
+            let index = plan.next_installment().unwrap();
+            plan.installments[index].paid_at = Some(now);
+
+            let mut order = self.get_order(plan.order_id.clone());
+            order.amount += amount;
+            // taxed in the jurisdiction of the down payment
+            order.tax += order.tax_jurisdiction.as_ref()
+                .map_or(0, |jurisdiction| self.internal_compute_tax(jurisdiction, &plan.merchant_id, &[], amount));
+            order.received_amount += payment.received_amount;
+            if plan.next_installment().is_none() {
+                plan.status = PlanStatus::Completed;
+                order.is_completed = true;
+                self.internal_mint_reward(&mut order);
+                self.internal_mint_receipt(&order);
+            }
+            self.orders.insert(&plan.order_id, &order);
+            if order.is_completed {
+                self.internal_call_payment_hook(&order);
+            }
+        }
+        self.installment_plans.insert(&plan.order_id, &plan);
+        let order = self.get_order(plan.order_id.clone());
+        self.internal_credit_merchant(&order, amount);
+
+        payment.received_amount - amount
+    }
+}
@@ -1,35 +1,168 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::json_types::U128;
-use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{env, AccountId, near_bindgen, PanicOnDefault, BorshStorageKey,
+use near_sdk::{env, AccountId, Balance, CryptoHash, Duration, near_bindgen, PanicOnDefault, BorshStorageKey,
                Promise, PromiseOrValue, PromiseResult, Gas, ext_contract};
-use near_sdk::collections::{LookupMap};
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap, UnorderedSet, Vector};
 
 mod order;
-use order::{PaymentMethod, Order};
+use order::{PaymentMethod, PriceSource, Order, Payment};
 mod ft_contract;
-use ft_contract::*;
+pub use ft_contract::*;
+mod multisig;
+use multisig::*;
+mod price_feed;
+use price_feed::*;
+mod quote;
+use quote::*;
+mod tokens;
+pub use tokens::*;
+mod merchant;
+use merchant::*;
+mod checkout;
+use checkout::*;
+mod line_item;
+use line_item::*;
+mod product;
+use product::*;
+mod coupon;
+use coupon::*;
+mod loyalty;
+pub use loyalty::*;
+mod gift_card;
+use gift_card::*;
+mod campaign;
+use campaign::*;
+mod installment;
+use installment::*;
+mod refund_batch;
+use refund_batch::*;
+mod receipt;
+pub use receipt::*;
+mod nft_payment;
+pub use nft_payment::*;
+mod payment_hook;
+use payment_hook::*;
+mod merchant_balance;
+use merchant_balance::*;
+mod risk;
+use risk::*;
+mod tax;
+use tax::*;
+mod fulfilment;
+use fulfilment::*;
+mod returns;
+use returns::*;
+mod review;
+use review::*;
+mod referral;
+mod migration;
 
 pub type OrderId = String;
 pub const TRANSFER_GAS: Gas = Gas(10_000_000_000_000); //
 
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 #[near_bindgen]
-struct EcommerceContract {
+pub struct EcommerceContract {
     pub owner_id: AccountId,
     pub ft_contract_id: AccountId,
-    pub orders: LookupMap<OrderId, Order>
+    pub orders: LookupMap<OrderId, Order>,
+    pub multisig_config: Option<MultisigConfig>,
+    pub multisig_requests: LookupMap<RequestId, MultisigRequest>,
+    pub next_request_id: RequestId,
+    pub oracle_id: Option<AccountId>,
+    pub max_price_age: Duration,
+    pub prices: LookupMap<AssetId, PriceData>,
+    pub invoices: LookupMap<OrderId, Invoice>,
+    pub quote_ttl: Duration,
+    pub quotes: LookupMap<(OrderId, AssetId), Quote>,
+    pub accepted_tokens: UnorderedMap<AccountId, TokenInfo>,
+    pub merchants: LookupMap<AccountId, Merchant>,
+    pub used_nonces: UsedNonces,
+    pub products: LookupMap<ProductKey, Product>,
+    pub reservation_ttl: Duration,
+    pub coupons: LookupMap<CouponKey, Coupon>,
+    pub coupon_usages: LookupMap<(AccountId, String, AccountId), u32>,
+    pub loyalty_config: Option<LoyaltyConfig>,
+    pub gift_cards: LookupMap<CryptoHash, GiftCard>,
+    pub campaigns: LookupMap<CampaignId, Campaign>,
+    pub campaign_orders: LookupMap<CampaignId, Vector<OrderId>>,
+    pub installment_plans: LookupMap<OrderId, InstallmentPlan>,
+    pub receipts: LookupMap<TokenId, Receipt>,
+    pub receipts_per_owner: LookupMap<AccountId, UnorderedSet<TokenId>>,
+    pub accepted_nfts: UnorderedMap<AccountId, NftCollection>,
+    pub merchant_balances: LookupMap<BalanceKey, MerchantBalance>,
+    pub pending_releases: LookupMap<(AccountId, Option<AccountId>, OrderId), PendingRelease>,
+    pub release_queue: LookupMap<(AccountId, Option<AccountId>, u64), OrderId>,
+    pub risk_limits: LookupMap<Option<AccountId>, RiskLimits>,
+    pub velocity_limit: Option<VelocityLimit>,
+    pub denied_accounts: LookupSet<AccountId>,
+    pub payer_activities: LookupMap<AccountId, PayerActivity>,
+    pub tax_rates: LookupMap<TaxRateKey, u16>,
+    pub tax_balances: LookupMap<(String, Option<AccountId>), Balance>,
+    pub returns: LookupMap<OrderId, ReturnRequest>,
+    pub reviews: LookupMap<OrderId, Review>,
+    pub merchant_ratings: LookupMap<AccountId, RatingSummary>,
+    pub product_ratings: LookupMap<ProductKey, RatingSummary>
 }
 
 #[derive(BorshDeserialize, BorshSerialize, BorshStorageKey)]
 enum StorageKey {
-    OrderKey
+    OrderKey,
+    MultisigRequests,
+    Prices,
+    Invoices,
+    AcceptedTokens,
+    Merchants,
+    UsedNonces,
+    Products,
+    Coupons,
+    CouponUsages,
+    GiftCards,
+    Campaigns,
+    CampaignOrderLists,
+    CampaignOrders { campaign_hash: CryptoHash },
+    InstallmentPlans,
+    Receipts,
+    ReceiptOwners,
+    ReceiptsPerOwner { account_hash: CryptoHash },
+    AcceptedNfts,
+    MerchantBalances,
+    RiskLimits,
+    DeniedAccounts,
+    PayerActivities,
+    TaxRates,
+    TaxBalances,
+    Returns,
+    Reviews,
+    MerchantRatings,
+    ProductRatings,
+    PendingReleases,
+    ReleaseQueue,
+    NonceQueue,
+    Quotes
 }
 
 
 #[ext_contract(ext_self)]
 pub trait ExtEcommerceContract {
     fn pay_order_callbacks(&mut self, order_id: OrderId) -> PromiseOrValue<U128>;
+    fn on_ft_metadata(&mut self, token_id: AccountId);
+    fn on_refund_line_items(&mut self, order_id: OrderId, items: Vec<LineItemRefund>, amount: U128) -> U128;
+    fn on_reward_minted(&mut self, order_id: OrderId);
+    fn on_campaign_settled(&mut self, campaign_id: CampaignId) -> U128;
+    fn on_refund_batch(&mut self, groups: Vec<Vec<OrderId>>, outcomes: Vec<RefundOutcome>, next_index: Option<u32>) -> RefundBatchReport;
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<std::collections::HashMap<AccountId, u64>>
+    ) -> bool;
+    fn on_payment_hook(&mut self, order_id: OrderId, on_failure: HookFailure);
+    fn on_merchant_withdraw(&mut self, merchant_id: AccountId, token: Option<AccountId>, amount: U128) -> U128;
+    fn on_tax_withdraw(&mut self, jurisdiction: String, token: Option<AccountId>, amount: U128) -> U128;
+    fn on_refund_return(&mut self, order_id: OrderId) -> U128;
+    fn on_nft_settled(&mut self, order_id: OrderId);
 }
 
 
@@ -41,17 +174,82 @@ impl ExtEcommerceContract for EcommerceContract {
         match env::promise_result(0) {
             PromiseResult::NotReady => unreachable!(),
             PromiseResult::Successful(_value) => {
+                self.internal_on_order_refunded(order_id);
                 PromiseOrValue::Value(U128(0))
             },
             PromiseResult::Failed => {
-                // update refund status
+                // update refund status, the merchant holds the amount again
                 let mut order = self.get_order(order_id.clone());
                 order.is_refund = false;
                 self.orders.insert(&order_id, &order);
-                PromiseOrValue::Value(U128(order.amount))
+                let amount = order.amount - order.refunded_amount;
+                self.internal_credit_merchant(&order, amount);
+                self.internal_on_campaign_refund_failed(&order);
+                PromiseOrValue::Value(U128(amount))
             }
         }
     }
+
+    #[private]
+    fn on_ft_metadata(&mut self, token_id: AccountId) {
+        self.internal_on_ft_metadata(token_id)
+    }
+
+    #[private]
+    fn on_refund_line_items(&mut self, order_id: OrderId, items: Vec<LineItemRefund>, amount: U128) -> U128 {
+        self.internal_on_refund_line_items(order_id, items, amount)
+    }
+
+    #[private]
+    fn on_reward_minted(&mut self, order_id: OrderId) {
+        self.internal_on_reward_minted(order_id)
+    }
+
+    #[private]
+    fn on_campaign_settled(&mut self, campaign_id: CampaignId) -> U128 {
+        self.internal_on_campaign_settled(campaign_id)
+    }
+
+    #[private]
+    fn on_refund_batch(&mut self, groups: Vec<Vec<OrderId>>, outcomes: Vec<RefundOutcome>, next_index: Option<u32>) -> RefundBatchReport {
+        self.internal_on_refund_batch(groups, outcomes, next_index)
+    }
+
+    #[private]
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        _approved_account_ids: Option<std::collections::HashMap<AccountId, u64>>
+    ) -> bool {
+        self.internal_nft_resolve_transfer(previous_owner_id, receiver_id, token_id)
+    }
+
+    #[private]
+    fn on_payment_hook(&mut self, order_id: OrderId, on_failure: HookFailure) {
+        self.internal_on_payment_hook(order_id, on_failure)
+    }
+
+    #[private]
+    fn on_merchant_withdraw(&mut self, merchant_id: AccountId, token: Option<AccountId>, amount: U128) -> U128 {
+        self.internal_on_merchant_withdraw(merchant_id, token, amount)
+    }
+
+    #[private]
+    fn on_tax_withdraw(&mut self, jurisdiction: String, token: Option<AccountId>, amount: U128) -> U128 {
+        self.internal_on_tax_withdraw(jurisdiction, token, amount)
+    }
+
+    #[private]
+    fn on_refund_return(&mut self, order_id: OrderId) -> U128 {
+        self.internal_on_refund_return(order_id)
+    }
+
+    #[private]
+    fn on_nft_settled(&mut self, order_id: OrderId) {
+        self.internal_on_nft_settled(order_id)
+    }
 }
 
 /**
@@ -64,92 +262,458 @@ impl ExtEcommerceContract for EcommerceContract {
 impl EcommerceContract {
     #[init]
     pub fn new(owner_id: AccountId, ft_contract_id: AccountId) -> Self {
-        Self { 
-            owner_id,
-            ft_contract_id,
-            orders: LookupMap::new(StorageKey::OrderKey)
-        }
+        let mut this = Self {
+            owner_id: owner_id.clone(),
+            ft_contract_id: ft_contract_id.clone(),
+            orders: LookupMap::new(StorageKey::OrderKey),
+            multisig_config: None,
+            multisig_requests: LookupMap::new(StorageKey::MultisigRequests),
+            next_request_id: 0,
+            oracle_id: None,
+            max_price_age: DEFAULT_MAX_PRICE_AGE,
+            prices: LookupMap::new(StorageKey::Prices),
+            invoices: LookupMap::new(StorageKey::Invoices),
+            quote_ttl: DEFAULT_QUOTE_TTL,
+            quotes: LookupMap::new(StorageKey::Quotes),
+            accepted_tokens: UnorderedMap::new(StorageKey::AcceptedTokens),
+            merchants: LookupMap::new(StorageKey::Merchants),
+            used_nonces: UsedNonces::new(StorageKey::UsedNonces, StorageKey::NonceQueue),
+            products: LookupMap::new(StorageKey::Products),
+            reservation_ttl: DEFAULT_RESERVATION_TTL,
+            coupons: LookupMap::new(StorageKey::Coupons),
+            coupon_usages: LookupMap::new(StorageKey::CouponUsages),
+            loyalty_config: None,
+            gift_cards: LookupMap::new(StorageKey::GiftCards),
+            campaigns: LookupMap::new(StorageKey::Campaigns),
+            campaign_orders: LookupMap::new(StorageKey::CampaignOrderLists),
+            installment_plans: LookupMap::new(StorageKey::InstallmentPlans),
+            receipts: LookupMap::new(StorageKey::Receipts),
+            receipts_per_owner: LookupMap::new(StorageKey::ReceiptOwners),
+            accepted_nfts: UnorderedMap::new(StorageKey::AcceptedNfts),
+            merchant_balances: LookupMap::new(StorageKey::MerchantBalances),
+            pending_releases: LookupMap::new(StorageKey::PendingReleases),
+            release_queue: LookupMap::new(StorageKey::ReleaseQueue),
+            risk_limits: LookupMap::new(StorageKey::RiskLimits),
+            velocity_limit: None,
+            denied_accounts: LookupSet::new(StorageKey::DeniedAccounts),
+            payer_activities: LookupMap::new(StorageKey::PayerActivities),
+            tax_rates: LookupMap::new(StorageKey::TaxRates),
+            tax_balances: LookupMap::new(StorageKey::TaxBalances),
+            returns: LookupMap::new(StorageKey::Returns),
+            reviews: LookupMap::new(StorageKey::Reviews),
+            merchant_ratings: LookupMap::new(StorageKey::MerchantRatings),
+            product_ratings: LookupMap::new(StorageKey::ProductRatings)
+        };
+        // The owner sells as the default merchant of ad-hoc orders
+        this.internal_register_merchant(owner_id, None);
+        this.internal_add_accepted_token(ft_contract_id);
+        this
     }
 
+    /*
+     * Pay an order with NEAR
+     * - Invoiced orders are charged from the invoice fiat price, order_amount only applies to ad-hoc orders
+     * - Orders with an installment plan are charged the down payment or the next installment
+     * - quote_id (see get_quote) charges an invoiced order the quoted amount, the payment is rejected once the quote expired
+     * - max_amount rejects the payment if the price moved above it
+     * - signed_order: order signed by the merchant backend, charged at the signed amount
+     * - line_items: cart lines of an ad-hoc order without tax, must add up to order_amount
+     * - coupon_code: coupon of the order's merchant, reduces the amount to pay
+     * - gift_card: buy a gift card with this order, or pay the order with a gift card first
+     * - campaign_id: pre-order of a crowdfunding campaign, held until the campaign deadline
+     * - tip: voluntary tip on top of the order amount, sent to the merchant tip recipient
+     * - referrer: account earning the merchant commission on this order, see set_commission_rate
+     * - The merchant payment hook, if any, is called with the paid order
+     * - Payments breaking the risk rules (see set_risk_limits) are rejected
+     * - Return the change to the payer
+     */
     #[payable]
-    pub fn pay_order(&mut self, order_id: OrderId, order_amount: U128) -> PromiseOrValue<U128> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn pay_order(
+        &mut self,
+        order_id: OrderId,
+        order_amount: U128,
+        quote_id: Option<String>,
+        max_amount: Option<U128>,
+        signed_order: Option<SignedOrder>,
+        line_items: Option<Vec<LineItem>>,
+        coupon_code: Option<String>,
+        gift_card: Option<GiftCardUse>,
+        campaign_id: Option<CampaignId>,
+        tip: Option<U128>,
+        referrer: Option<AccountId>
+    ) -> PromiseOrValue<U128> {
         // Lay thong tin so NEAR deposit cua user env::attached_deposit()
-        assert!(env::attached_deposit() >= order_amount.0, "ERROR_DEPOSIT_NOT_ENOUGH");
-
-        // Luu tru lai thong tin thanh toan cua user
-        let order: Order = Order { 
-            order_id: order_id.clone(),
+        let change = self.internal_pay_order(Payment {
+            order_id,
             payer_id: env::signer_account_id(),
             payment_method: PaymentMethod::Near,
-            amount: order_amount.0,
+            token_id: None,
+            order_amount: order_amount.0,
             received_amount: env::attached_deposit(),
-            is_completed: true,
-            is_refund: false,
-            created_at: env::block_timestamp()
-        };
-
-        self.orders.insert(&order_id, &order);
+            quote_id,
+            max_amount: max_amount.map(|amount| amount.0),
+            signed_order,
+            line_items,
+            coupon_code,
+            gift_card,
+            campaign_id,
+            tip: tip.map_or(0, |tip| tip.0),
+            nft_payment: None,
+            referrer
+        });
 
         // Tra lai tien thua cho user
-        if env::attached_deposit() > order_amount.0 {
-            Promise::new(env::signer_account_id()).transfer(env::attached_deposit() - order_amount.0);
-            PromiseOrValue::Value(U128(env::attached_deposit() - order_amount.0))
+        if change > 0 {
+            Promise::new(env::signer_account_id()).transfer(change);
+            PromiseOrValue::Value(U128(change))
         } else {
             PromiseOrValue::Value(U128(0))
         }
     }
 
+    /*
+     * Pay several orders with one NEAR deposit, e.g. a cart with several merchants
+     * - The deposit is spent on the orders in the given order, all orders are paid or none
+     * - Return the remainder to the payer
+     */
+    #[payable]
+    pub fn pay_orders(&mut self, orders: Vec<(OrderId, U128)>) -> PromiseOrValue<U128> {
+        let change = self.internal_pay_orders(
+            env::signer_account_id(),
+            PaymentMethod::Near,
+            None,
+            orders,
+            env::attached_deposit()
+        );
+
+        if change > 0 {
+            Promise::new(env::signer_account_id()).transfer(change);
+        }
+        PromiseOrValue::Value(U128(change))
+    }
+
     /*
      * Refund money to user
      * - Check requested account is owner or not
-     * - Check order is completed and is refund
-     * - Update order status and refund money to user
+     * - Refunds above the multisig threshold only create a pending request
+     * - Otherwise update order status and refund money to user
      */
     pub fn refund(&mut self, order_id: OrderId) -> PromiseOrValue<U128> {
-        assert_eq!(env::predecessor_account_id(), self.owner_id);
+        self.assert_owner();
 
-        let mut order = self.get_order(order_id.clone());
+        let order = self.get_order(order_id.clone());
         assert!(order.is_completed && !order.is_refund);
-        order.is_refund = true;
+        assert!(order.issued_gift_card.is_none(), "ERROR_GIFT_CARD_NOT_REFUNDABLE");
+        self.assert_campaign_refundable(&order);
+        Self::assert_nft_refundable(&order);
 
-        self.orders.insert(&order_id, &order);
+        if self.requires_multisig(&order.token_id, order.amount) {
+            self.internal_add_request(MultisigAction::Refund { order_id });
+            return PromiseOrValue::Value(U128(0));
+        }
 
-        if order.amount > 0 {
-            // Cross contract call
-            match order.payment_method {
-                PaymentMethod::Near => {
-                    let promise = Promise::new(order.payer_id)
-                        .transfer(order.amount)
-                        .then(
-                            ext_self::ext(env::current_account_id())
-                                .with_attached_deposit(0)
-                                .with_static_gas(TRANSFER_GAS)
-                                .pay_order_callbacks(order_id)
-                        );
-                    PromiseOrValue::Promise(promise)
-                }
-                PaymentMethod::FungibleToken => {
-                    let promise = ext_ft::ext(self.ft_contract_id.clone())
-                        .with_attached_deposit(1)
-                        .with_static_gas(TRANSFER_GAS)
-                        .ft_transfer(order.payer_id, U128(order.amount), Some("Refund order from payment contract".to_owned()))
-                        .then(
-                            ext_self::ext(env::current_account_id())
-                                .with_attached_deposit(0)
-                                .with_static_gas(TRANSFER_GAS)
-                                .pay_order_callbacks(order_id)
-                        );
-                    PromiseOrValue::Promise(promise)
+        self.internal_refund(order_id)
+    }
+
+    /*
+     * Withdraw NEAR (token = None) or FT from the available balance of the calling merchant or referrer
+     * - Payments and commissions are only available after the merchant hold period, see get_merchant_balance
+     * - Once the amount withdrawn in the token today is above the multisig threshold, withdrawals only create
+     *   a pending request, the amount is reserved until the request is executed or deleted
+     */
+    pub fn withdraw(&mut self, token: Option<AccountId>, amount: U128) -> PromiseOrValue<U128> {
+        let account_id = env::predecessor_account_id();
+        assert!(amount.0 > 0, "ERROR_INVALID_AMOUNT");
+        self.payment_method_of(&token);
+        let withdrawn_today = self.internal_debit_withdrawal(&account_id, &token, amount.0);
+
+        if self.requires_multisig(&token, withdrawn_today) {
+            self.internal_add_request(MultisigAction::Withdraw {
+                token,
+                receiver_id: account_id,
+                amount
+            });
+            return PromiseOrValue::Value(U128(0));
+        }
+
+        self.internal_withdraw(token, account_id, amount)
+    }
+
+    pub fn get_order(&self, order_id: OrderId) -> Order {
+        self.orders.get(&order_id).expect("NOT_FOUND_ORDER_ID")
+    }
+}
+
+impl EcommerceContract {
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "ERROR_NOT_OWNER");
+    }
+
+    /*
+     * Save the payment of an order, shared by pay_order and ft_on_transfer
+     * - Return the change to give back to the payer
+     */
+    pub(crate) fn internal_pay_order(&mut self, payment: Payment) -> Balance {
+        if self.installment_plans.get(&payment.order_id).is_some() {
+            return self.internal_pay_installment(payment);
+        }
+
+        // check order status: completed or not completed
+        if let Some(order) = self.orders.get(&payment.order_id) {
+            assert!(!order.is_completed, "ERROR_ORDER_ALREADY_PAID");
+        }
 
+        // Price from the invoice, the merchant signed order, or the ad-hoc order amount
+        let invoice = self.invoices.get(&payment.order_id);
+        let (amount, fiat_amount, rate, merchant_id, line_items, price_source) = match (invoice, &payment.signed_order) {
+            (Some(invoice), None) => {
+                assert!(payment.line_items.is_none(), "ERROR_LINE_ITEMS_NOT_ALLOWED");
+                // product stock was reserved when the invoice was created
+                if let Some(expires_at) = invoice.expires_at {
+                    assert!(env::block_timestamp() <= expires_at, "ERROR_INVOICE_EXPIRED");
                 }
+                let (amount, rate) = match &payment.quote_id {
+                    Some(quote_id) => self.internal_use_quote(&payment, quote_id),
+                    None => self.internal_invoice_amount(&invoice, &payment.token_id)
+                };
+                let fiat_amount = Some(invoice.amount).filter(|_| invoice.unit == PriceUnit::Fiat);
+                (amount, fiat_amount, rate, invoice.merchant_id, invoice.line_items, PriceSource::Invoice)
+            }
+            (None, Some(signed_order)) => {
+                assert!(payment.line_items.is_none(), "ERROR_LINE_ITEMS_NOT_ALLOWED");
+                assert!(payment.quote_id.is_none(), "ERROR_QUOTE_NOT_ALLOWED");
+                let amount = self.internal_verify_signed_order(&payment, signed_order);
+                let mut line_items = signed_order.payload.line_items.clone();
+                assert_line_items(&mut line_items, amount);
+                self.internal_take_stock(&signed_order.payload.merchant_id, &line_items);
+                (amount, None, None, signed_order.payload.merchant_id.clone(), line_items, PriceSource::SignedOrder)
             }
+            (None, None) => {
+                assert!(payment.quote_id.is_none(), "ERROR_QUOTE_NOT_ALLOWED");
+                let mut line_items = payment.line_items.clone().unwrap_or_default();
+                // tax is set by the merchant jurisdiction rules, never by the payer
+                assert!(line_items.iter().all(|line| line.tax.0 == 0), "ERROR_LINE_TAX_NOT_ALLOWED");
+                assert_line_items(&mut line_items, payment.order_amount);
+                self.assert_product_prices(&self.owner_id, &line_items, &payment.token_id);
+                self.internal_take_stock(&self.owner_id.clone(), &line_items);
+                (payment.order_amount, None, None, self.owner_id.clone(), line_items, PriceSource::Payer)
+            }
+            (Some(_), Some(_)) => env::panic_str("ERROR_ORDER_ALREADY_EXISTS")
+        };
+
+        let discount = match &payment.coupon_code {
+            Some(code) => self.internal_redeem_coupon(&merchant_id, code, &payment, amount),
+            None => 0
+        };
+        let amount = amount - discount;
+
+        let gift_card_payment = match &payment.gift_card {
+            Some(GiftCardUse::Redeem { code_hash }) => {
+                Some(self.internal_redeem_gift_card(*code_hash, &payment.payer_id, &merchant_id, &payment.token_id, amount))
+            }
+            _ => None
+        };
+        let amount = amount - gift_card_payment.as_ref().map_or(0, |gift_card_payment| gift_card_payment.amount.0);
+
+        self.assert_max_amount(&payment, amount);
+        assert!(payment.received_amount >= amount + payment.tip, "ERROR_DEPOSIT_NOT_ENOUGH");
+        // rejected payments panic so the deposit or the transferred tokens are returned
+        self.internal_check_risk(&payment.payer_id, &payment.token_id, amount, true);
+
+        // campaign payments are held in the campaign escrow and paid out in full by finalize_campaign,
+        // NFT payments hold no fungible funds, neither go through the merchant balance so they are not taxed
+        let tax_jurisdiction = Some(self.get_merchant(merchant_id.clone()))
+            .filter(|_| payment.campaign_id.is_none() && payment.nft_payment.is_none())
+            .and_then(|merchant| merchant.jurisdiction);
+        // line taxes of merchant orders would be counted twice with the jurisdiction rules
+        assert!(
+            tax_jurisdiction.is_none() || line_items.iter().all(|line| line.tax.0 == 0),
+            "ERROR_LINE_TAX_NOT_ALLOWED"
+        );
+        let tax = tax_jurisdiction.as_ref()
+            .map_or(0, |jurisdiction| self.internal_compute_tax(jurisdiction, &merchant_id, &line_items, amount));
+        let commission = self.internal_compute_commission(&payment, &merchant_id, amount - tax, price_source);
+
+        // Luu tru lai thong tin thanh toan cua user
+        let Payment { order_id, payer_id, payment_method, token_id, received_amount, coupon_code, gift_card, campaign_id, tip, nft_payment, referrer, .. } = payment;
+        let mut order: Order = Order {
+            order_id: order_id.clone(),
+            merchant_id,
+            payer_id,
+            payment_method,
+            token_id,
+            amount,
+            received_amount,
+            is_completed: true,
+            is_refund: false,
+            created_at: env::block_timestamp(),
+            fiat_amount,
+            rate,
+            line_items,
+            refunded_amount: 0,
+            coupon_code,
+            discount,
+            reward_token_id: None,
+            reward_amount: 0,
+            gift_card_payment,
+            issued_gift_card: None,
+            campaign_id,
+            tip,
+            nft_payment,
+            tax,
+            tax_jurisdiction,
+            fulfilment: vec![],
+            delivery_confirmed_at: None,
+            referrer_id: referrer,
+            commission,
+            price_source
+        };
+
+        if let Some(GiftCardUse::Purchase { code_hash, holder_id }) = gift_card {
+            let holder_id = holder_id.unwrap_or_else(|| order.payer_id.clone());
+            self.internal_issue_gift_card(code_hash, order.merchant_id.clone(), holder_id, order.token_id.clone(), amount, Some(order_id.clone()));
+            order.issued_gift_card = Some(code_hash);
+        }
+        if let Some(campaign_id) = &order.campaign_id {
+            assert!(order.issued_gift_card.is_none(), "ERROR_GIFT_CARD_NOT_ALLOWED");
+            self.internal_add_campaign_order(campaign_id, &order);
+        }
+
+        self.internal_mint_reward(&mut order);
+        self.internal_mint_receipt(&order);
+        self.orders.insert(&order_id, &order);
+        self.internal_credit_merchant(&order, amount);
+
+        if tip > 0 {
+            let merchant = self.get_merchant(order.merchant_id.clone());
+            let tip_recipient = merchant.tip_recipient.unwrap_or(merchant.merchant_id);
+            self.internal_transfer(order.token_id.clone(), tip_recipient, tip, "Tip from payment contract");
+        }
+        self.internal_call_payment_hook(&order);
+
+        received_amount - amount - tip
+    }
+
+    /*
+     * Pay each order from what is left of the received amount, shared by pay_orders and batch ft_on_transfer
+     * - Return the remainder to give back to the payer
+     */
+    pub(crate) fn internal_pay_orders(
+        &mut self,
+        payer_id: AccountId,
+        payment_method: PaymentMethod,
+        token_id: Option<AccountId>,
+        orders: Vec<(OrderId, U128)>,
+        received_amount: Balance
+    ) -> Balance {
+        assert!(!orders.is_empty(), "ERROR_NO_ORDERS");
+
+        let mut remaining = received_amount;
+        for (order_id, order_amount) in orders {
+            let change = self.internal_pay_order(Payment {
+                order_id: order_id.clone(),
+                payer_id: payer_id.clone(),
+                payment_method,
+                token_id: token_id.clone(),
+                order_amount: order_amount.0,
+                received_amount: remaining,
+                quote_id: None,
+                max_amount: None,
+                signed_order: None,
+                line_items: None,
+                coupon_code: None,
+                gift_card: None,
+                campaign_id: None,
+                tip: 0,
+                nft_payment: None,
+                referrer: None
+            });
+            // the change pays the next orders, each order only records the share it used
+            let mut order = self.get_order(order_id.clone());
+            order.received_amount -= change;
+            self.orders.insert(&order_id, &order);
+            remaining = change;
+        }
+        remaining
+    }
+
+    pub(crate) fn internal_refund(&mut self, order_id: OrderId) -> PromiseOrValue<U128> {
+        let mut order = self.get_order(order_id.clone());
+        assert!(order.is_completed && !order.is_refund, "ERROR_ORDER_NOT_REFUNDABLE");
+        assert!(order.issued_gift_card.is_none(), "ERROR_GIFT_CARD_NOT_REFUNDABLE");
+        Self::assert_nft_refundable(&order);
+        order.is_refund = true;
+        // the gift card share goes back to the card as store credit
+        self.internal_refund_gift_card(&mut order);
+
+        self.orders.insert(&order_id, &order);
+
+        // Line items refunded before are not refunded twice
+        let amount = order.amount - order.refunded_amount;
+        self.internal_debit_merchant(&order, amount);
+        if amount > 0 || order.nft_payment.is_some() {
+            // Cross contract call, NFT payments get their NFT back
+            let promise = match order.nft_payment {
+                Some(nft_payment) => self.internal_transfer_nft(nft_payment, order.payer_id, "Refund order from payment contract"),
+                None => self.internal_transfer(order.token_id, order.payer_id, amount, "Refund order from payment contract")
+            };
+            let promise = promise
+                .then(
+                    ext_self::ext(env::current_account_id())
+                        .with_attached_deposit(0)
+                        .with_static_gas(REFUND_CALLBACK_GAS)
+                        .pay_order_callbacks(order_id)
+                );
+            PromiseOrValue::Promise(promise)
         } else {
+            self.internal_on_order_refunded(order_id);
             PromiseOrValue::Value(U128(0))
         }
     }
 
-    pub fn get_order(&self, order_id: OrderId) -> Order {
-        self.orders.get(&order_id).expect("NOT_FOUND_ORDER_ID")
+    /*
+     * Put the products not refunded by line before back in stock and give back the coupon and reward
+     */
+    pub(crate) fn internal_on_order_refunded(&mut self, order_id: OrderId) {
+        let order = self.get_order(order_id.clone());
+        for line in order.line_items.iter() {
+            self.internal_restore_stock(&order.merchant_id, &line.sku, line.quantity - line.refunded_quantity);
+        }
+        self.internal_restore_coupon(&order);
+        let remaining_amount = order.amount - order.refunded_amount;
+        self.internal_void_receipt(&order_id);
+        self.internal_clawback_reward(order_id, remaining_amount, remaining_amount);
+    }
+
+    /*
+     * Withdraw from the available balance of the merchant or referrer receiver_id, given back if the transfer fails
+     */
+    /*
+     * Send an amount already debited from the available balance of receiver_id
+     */
+    pub(crate) fn internal_withdraw(&mut self, token: Option<AccountId>, receiver_id: AccountId, amount: U128) -> PromiseOrValue<U128> {
+        let promise = self.internal_transfer(token.clone(), receiver_id.clone(), amount.0, "Withdraw from payment contract")
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_attached_deposit(0)
+                    .with_static_gas(TRANSFER_GAS)
+                    .on_merchant_withdraw(receiver_id, token, amount)
+            );
+        PromiseOrValue::Promise(promise)
+    }
+
+    /*
+     * Send NEAR (token = None) or FT from the contract balance
+     */
+    pub(crate) fn internal_transfer(&self, token: Option<AccountId>, receiver_id: AccountId, amount: Balance, memo: &str) -> Promise {
+        match token {
+            None => Promise::new(receiver_id).transfer(amount),
+            Some(token_id) => ext_ft::ext(token_id)
+                .with_attached_deposit(1)
+                .with_static_gas(TRANSFER_GAS)
+                .ft_transfer(receiver_id, U128(amount), Some(memo.to_owned()))
+        }
     }
 }
 
@@ -157,7 +721,7 @@ impl EcommerceContract {
 mod tests {
     use super::*;
     use near_sdk::test_utils::{VMContextBuilder, accounts};
-    use near_sdk::{testing_env, MockedBlockchain};
+    use near_sdk::testing_env;
 
     fn get_context(is_view: bool) -> VMContextBuilder {
         let mut builder = VMContextBuilder::new();
@@ -170,6 +734,153 @@ mod tests {
         builder
     }
 
+    /*
+     * Contract owned by alice, who also signs the next calls with attached_deposit
+     */
+    fn new_contract(context: &mut VMContextBuilder, attached_deposit: Balance) -> EcommerceContract {
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(attached_deposit);
+
+        testing_env!(context.build());
+
+        EcommerceContract::new(alice, accounts(1))
+    }
+
+    /*
+     * Arguments of pay_order, only the ones a test sets are passed
+     */
+    #[derive(Default)]
+    struct PayOrder {
+        order_id: OrderId,
+        order_amount: Balance,
+        quote_id: Option<String>,
+        max_amount: Option<U128>,
+        signed_order: Option<SignedOrder>,
+        line_items: Option<Vec<LineItem>>,
+        coupon_code: Option<String>,
+        gift_card: Option<GiftCardUse>,
+        campaign_id: Option<CampaignId>,
+        tip: Option<U128>,
+        referrer: Option<AccountId>
+    }
+
+    impl PayOrder {
+        fn new(order_id: &str, order_amount: Balance) -> Self {
+            Self { order_id: order_id.to_owned(), order_amount, ..Default::default() }
+        }
+
+        fn quote(mut self, quote_id: String) -> Self {
+            self.quote_id = Some(quote_id);
+            self
+        }
+
+        fn max_amount(mut self, max_amount: Balance) -> Self {
+            self.max_amount = Some(U128(max_amount));
+            self
+        }
+
+        fn signed_order(mut self, signed_order: SignedOrder) -> Self {
+            self.signed_order = Some(signed_order);
+            self
+        }
+
+        fn line_items(mut self, line_items: Vec<LineItem>) -> Self {
+            self.line_items = Some(line_items);
+            self
+        }
+
+        fn coupon_code(mut self, coupon_code: String) -> Self {
+            self.coupon_code = Some(coupon_code);
+            self
+        }
+
+        fn gift_card(mut self, gift_card: GiftCardUse) -> Self {
+            self.gift_card = Some(gift_card);
+            self
+        }
+
+        fn campaign_id(mut self, campaign_id: CampaignId) -> Self {
+            self.campaign_id = Some(campaign_id);
+            self
+        }
+
+        fn tip(mut self, tip: Balance) -> Self {
+            self.tip = Some(U128(tip));
+            self
+        }
+
+        fn referrer(mut self, referrer: AccountId) -> Self {
+            self.referrer = Some(referrer);
+            self
+        }
+
+        fn pay(self, contract: &mut EcommerceContract) -> PromiseOrValue<U128> {
+            contract.pay_order(
+                self.order_id,
+                U128(self.order_amount),
+                self.quote_id,
+                self.max_amount,
+                self.signed_order,
+                self.line_items,
+                self.coupon_code,
+                self.gift_card,
+                self.campaign_id,
+                self.tip,
+                self.referrer
+            )
+        }
+    }
+
+    /*
+     * Run the next callback with only the gas its caller attached to it, later calls get the full gas again
+     */
+    fn callback_context(context: &mut VMContextBuilder, prepaid_gas: Gas, result: PromiseResult) {
+        testing_env!(
+            context.prepaid_gas(prepaid_gas).build(),
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result]
+        );
+        context.prepaid_gas(Gas(300_000_000_000_000));
+    }
+
+    /*
+     * Merchant alice ships the order
+     */
+    fn ship_order(context: &mut VMContextBuilder, contract: &mut EcommerceContract, order_id: &str) {
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        contract.update_fulfilment(order_id.to_owned(), FulfilmentStatus::Processing, None);
+        contract.update_fulfilment(order_id.to_owned(), FulfilmentStatus::Shipped, Some([1; 32].into()));
+    }
+
+    /*
+     * Merchant alice ships the order and its payer confirms the delivery
+     */
+    fn deliver_order(context: &mut VMContextBuilder, contract: &mut EcommerceContract, order_id: &str, payer_id: AccountId) {
+        ship_order(context, contract, order_id);
+        testing_env!(context.predecessor_account_id(payer_id).build());
+        contract.confirm_delivery(order_id.to_owned());
+    }
+
+    /*
+     * Accept the FT token_id with the decimals returned by its ft_metadata
+     */
+    fn accept_ft(context: &mut VMContextBuilder, contract: &mut EcommerceContract, token_id: AccountId, decimals: u8) {
+        let metadata = FungibleTokenMetadata {
+            spec: "ft-1.0.0".to_owned(),
+            name: "Token".to_owned(),
+            symbol: "TKN".to_owned(),
+            decimals
+        };
+        callback_context(context, FT_METADATA_GAS, PromiseResult::Successful(near_sdk::serde_json::to_vec(&metadata).unwrap()));
+        contract.on_ft_metadata(token_id);
+        testing_env!(context.build());
+    }
+
     #[test]
     fn test_pay_order() {
         let mut context = get_context(false);
@@ -185,7 +896,7 @@ mod tests {
 
         let mut contract = EcommerceContract::new(alice.clone(), ft_contract_id.clone());
         let order_amount = U128(1000);
-        contract.pay_order("order_1".to_owned(), order_amount);
+        PayOrder::new("order_1", order_amount.0).pay(&mut contract);
 
         let order = contract.get_order("order_1".to_owned());
 
@@ -212,6 +923,1799 @@ mod tests {
 
         let mut contract = EcommerceContract::new(alice.clone(), ft_contract_id.clone());
         let order_amount = U128(2000);
-        contract.pay_order("order_1".to_owned(), order_amount);
+        PayOrder::new("order_1", order_amount.0).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_refund_requires_multisig() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.account_balance(1_000_000_000)
+        .predecessor_account_id(alice.clone())
+        .attached_deposit(1_000_000_000)
+        .signer_account_id(alice.clone());
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        PayOrder::new("order_1", 1_000_000_000).pay(&mut contract);
+        contract.set_multisig_config(Some(multisig_config(2)));
+
+        contract.refund("order_1".to_owned());
+        assert!(!contract.get_order("order_1".to_owned()).is_refund);
+        assert_eq!(contract.get_request(0).proposer_id, alice);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.confirm_request(0);
+        assert!(!contract.get_order("order_1".to_owned()).is_refund);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.confirm_request(0);
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+    }
+
+    /*
+     * accounts(2) and accounts(3) approve amounts above 100 normalized units, 100_000_000 yoctoNEAR
+     */
+    fn multisig_config(num_confirmations: u32) -> MultisigConfig {
+        MultisigConfig {
+            approvers: vec![accounts(2), accounts(3)],
+            num_confirmations,
+            amount_threshold: U128(100),
+            request_lifetime: 1_000
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_REQUEST_EXPIRED")]
+    fn test_confirm_expired_request() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .attached_deposit(1_000_000_000)
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        PayOrder::new("order_1", 1_000_000_000).pay(&mut contract);
+        contract.set_multisig_config(Some(multisig_config(1)));
+        contract.refund("order_1".to_owned());
+
+        testing_env!(context.predecessor_account_id(accounts(2)).block_timestamp(1_000).build());
+        contract.confirm_request(0);
+    }
+
+    #[test]
+    fn test_multisig_threshold_in_normalized_units() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let usdc: AccountId = accounts(1);
+        let mut contract = new_contract(&mut context, 500 * 10u128.pow(24));
+        accept_ft(&mut context, &mut contract, usdc.clone(), 6);
+        contract.set_multisig_config(Some(MultisigConfig { amount_threshold: U128(1000 * 10u128.pow(18)), ..multisig_config(1) }));
+
+        // 500 NEAR is below the threshold of 1000 units
+        PayOrder::new("order_1", 500 * 10u128.pow(24)).pay(&mut contract);
+        contract.refund("order_1".to_owned());
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+
+        // 1500 USDC is above it
+        testing_env!(context.predecessor_account_id(usdc).build());
+        let msg = r#"{"order_id": "order_2", "order_amount": "1500000000"}"#.to_owned();
+        contract.ft_on_transfer(alice.clone(), U128(1_500_000_000), msg);
+        testing_env!(context.predecessor_account_id(alice).build());
+        contract.refund("order_2".to_owned());
+        assert!(!contract.get_order("order_2".to_owned()).is_refund);
+        assert!(matches!(contract.get_request(0).action, MultisigAction::Refund { .. }));
+    }
+
+    #[test]
+    fn test_withdraw_request_reserves_balance() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let mut contract = new_contract(&mut context, 1_000_000_000);
+        PayOrder::new("order_1", 1_000_000_000).pay(&mut contract);
+        contract.set_multisig_config(Some(multisig_config(1)));
+
+        testing_env!(context.block_timestamp(DEFAULT_HOLD_PERIOD).attached_deposit(0).build());
+        contract.withdraw(None, U128(600_000_000));
+        assert_eq!(contract.get_merchant_balance(alice.clone(), None).available, U128(400_000_000));
+
+        // deleting the request gives the reserved amount back
+        contract.delete_request(0);
+        assert_eq!(contract.get_merchant_balance(alice, None).available, U128(1_000_000_000));
+    }
+
+    #[test]
+    fn test_split_withdrawals_require_multisig() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let mut contract = new_contract(&mut context, 1_000_000_000);
+        PayOrder::new("order_1", 1_000_000_000).pay(&mut contract);
+        contract.set_multisig_config(Some(multisig_config(1)));
+
+        testing_env!(context.block_timestamp(DEFAULT_HOLD_PERIOD).attached_deposit(0).build());
+        contract.withdraw(None, U128(100_000_000));
+        assert!(contract.multisig_requests.get(&0).is_none());
+
+        // the second withdrawal of the day brings the total above the threshold
+        contract.withdraw(None, U128(50_000_000));
+        assert!(matches!(contract.get_request(0).action, MultisigAction::Withdraw { .. }));
+        assert_eq!(contract.get_merchant_balance(alice.clone(), None).withdrawn_today, U128(150_000_000));
+
+        testing_env!(context.block_timestamp(DEFAULT_HOLD_PERIOD + DAY).build());
+        contract.withdraw(None, U128(100_000_000));
+        assert!(contract.multisig_requests.get(&1).is_none());
+        assert_eq!(contract.get_merchant_balance(alice, None).available, U128(750_000_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_MERCHANT_BALANCE_NOT_ENOUGH")]
+    fn test_withdraw_request_without_balance() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+        contract.set_multisig_config(Some(multisig_config(1)));
+
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.withdraw(None, U128(600_000_000));
+    }
+
+    #[test]
+    fn test_pay_invoice_with_fiat_price() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        // 1 NEAR = $2.50
+        let rate = Price { multiplier: U128(25_000), decimals: 28 };
+        contract.push_prices(vec![AssetPrice { asset_id: NEAR_ASSET_ID.to_owned(), price: rate }]);
+        // $10.00
+        contract.create_invoice("order_1".to_owned(), U128(1_000), PriceUnit::Fiat, None);
+
+        testing_env!(context.attached_deposit(5 * 10u128.pow(24)).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.amount, 4 * 10u128.pow(24));
+        assert_eq!(order.fiat_amount, Some(1_000));
+        assert_eq!(order.rate, Some(rate));
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[should_panic(expected = "ERROR_PRICE_STALE")]
+    fn test_pay_invoice_with_stale_price() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.push_prices(vec![AssetPrice {
+            asset_id: NEAR_ASSET_ID.to_owned(),
+            price: Price { multiplier: U128(25_000), decimals: 28 }
+        }]);
+        contract.create_invoice("order_1".to_owned(), U128(1_000), PriceUnit::Fiat, None);
+
+        testing_env!(context.attached_deposit(5 * 10u128.pow(24)).block_timestamp(DEFAULT_MAX_PRICE_AGE + 1).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_QUOTE_EXPIRED")]
+    fn test_pay_invoice_with_expired_quote() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.set_price_feed(None, DEFAULT_QUOTE_TTL * 2);
+        contract.push_prices(vec![AssetPrice {
+            asset_id: NEAR_ASSET_ID.to_owned(),
+            price: Price { multiplier: U128(25_000), decimals: 28 }
+        }]);
+        contract.create_invoice("order_1".to_owned(), U128(1_000), PriceUnit::Fiat, None);
+
+        let quote = contract.get_quote("order_1".to_owned(), None);
+        assert_eq!(quote.amount, U128(4 * 10u128.pow(24)));
+
+        testing_env!(context.attached_deposit(5 * 10u128.pow(24)).block_timestamp(DEFAULT_QUOTE_TTL + 1).build());
+        PayOrder::new("order_1", 0).quote(quote.quote_id).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_INVALID_QUOTE_ID")]
+    fn test_pay_invoice_with_forged_quote() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.push_prices(vec![AssetPrice {
+            asset_id: NEAR_ASSET_ID.to_owned(),
+            price: Price { multiplier: U128(25_000), decimals: 28 }
+        }]);
+        contract.create_invoice("order_1".to_owned(), U128(1_000), PriceUnit::Fiat, None);
+
+        // a quote id built like get_quote ones but never issued
+        let expires_at = DEFAULT_QUOTE_TTL * 10;
+        let data = (env::current_account_id(), "order_1".to_owned(), NEAR_ASSET_ID.to_owned(), expires_at).try_to_vec().unwrap();
+        let quote_id = near_sdk::bs58::encode(env::sha256(&data)).into_string();
+
+        testing_env!(context.attached_deposit(5 * 10u128.pow(24)).build());
+        PayOrder::new("order_1", 0).quote(quote_id).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_pay_invoice_at_quoted_amount() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+        contract.push_prices(vec![AssetPrice {
+            asset_id: NEAR_ASSET_ID.to_owned(),
+            price: Price { multiplier: U128(25_000), decimals: 28 }
+        }]);
+        contract.create_invoice("order_1".to_owned(), U128(1_000), PriceUnit::Fiat, None);
+        let quote = contract.get_quote("order_1".to_owned(), None);
+        assert_eq!(quote.amount, U128(4 * 10u128.pow(24)));
+
+        // NEAR drops to $2.00 before the payment lands, the quote still returns the same amount
+        contract.push_prices(vec![AssetPrice {
+            asset_id: NEAR_ASSET_ID.to_owned(),
+            price: Price { multiplier: U128(20_000), decimals: 28 }
+        }]);
+        assert_eq!(contract.get_quote("order_1".to_owned(), None).quote_id, quote.quote_id);
+
+        testing_env!(context.attached_deposit(5 * 10u128.pow(24)).build());
+        PayOrder::new("order_1", 0).quote(quote.quote_id).pay(&mut contract);
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.amount, 4 * 10u128.pow(24));
+        assert_eq!(order.rate.unwrap().multiplier, U128(25_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_QUOTE_NOT_ALLOWED")]
+    fn test_pay_ad_hoc_order_with_quote() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        contract.push_prices(vec![AssetPrice {
+            asset_id: NEAR_ASSET_ID.to_owned(),
+            price: Price { multiplier: U128(25_000), decimals: 28 }
+        }]);
+        contract.create_invoice("order_1".to_owned(), U128(1_000), PriceUnit::Fiat, None);
+        let quote = contract.get_quote("order_1".to_owned(), None);
+
+        // the quote of an invoice does not price another order
+        PayOrder::new("order_2", 1000).quote(quote.quote_id).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_SLIPPAGE_EXCEEDED")]
+    fn test_pay_invoice_above_max_amount() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+        contract.push_prices(vec![AssetPrice {
+            asset_id: NEAR_ASSET_ID.to_owned(),
+            price: Price { multiplier: U128(25_000), decimals: 28 }
+        }]);
+        contract.create_invoice("order_1".to_owned(), U128(1_000), PriceUnit::Fiat, None);
+
+        // NEAR drops to $2.00 before the payment lands
+        contract.push_prices(vec![AssetPrice {
+            asset_id: NEAR_ASSET_ID.to_owned(),
+            price: Price { multiplier: U128(20_000), decimals: 28 }
+        }]);
+
+        testing_env!(context.attached_deposit(5 * 10u128.pow(24)).build());
+        PayOrder::new("order_1", 0).max_amount(4 * 10u128.pow(24)).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_pay_normalized_invoice_with_ft() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let usdc: AccountId = accounts(1);
+
+        testing_env!(context.predecessor_account_id(alice.clone()).build());
+        let mut contract = EcommerceContract::new(alice.clone(), usdc.clone());
+
+        accept_ft(&mut context, &mut contract, usdc.clone(), 6);
+        assert_eq!(contract.get_accepted_token(usdc.clone()).decimals, Some(6));
+
+        // 10 normalized units
+        contract.create_invoice("order_1".to_owned(), U128(10 * 10u128.pow(18)), PriceUnit::Normalized, None);
+
+        testing_env!(context.predecessor_account_id(usdc.clone()).build());
+        let msg = r#"{"order_id": "order_1", "order_amount": "0"}"#.to_owned();
+        contract.ft_on_transfer(alice, U128(10_000_000), msg);
+
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.amount, 10_000_000);
+        assert_eq!(order.token_id, Some(usdc));
+    }
+
+    fn sign_order(secret: &[u8; 32], payload: CheckoutPayload) -> (near_sdk::PublicKey, SignedOrder) {
+        use ed25519_dalek::Signer;
+        let secret = ed25519_dalek::SecretKey::from_bytes(secret).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        let keypair = ed25519_dalek::Keypair { secret, public };
+        let signature = keypair.sign(&payload.try_to_vec().unwrap());
+
+        let public_key = near_sdk::PublicKey::try_from([vec![0], public.to_bytes().to_vec()].concat()).unwrap();
+        (public_key, SignedOrder { payload, signature: signature.to_bytes().to_vec().into() })
+    }
+
+    #[test]
+    fn test_pay_signed_order() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let bob: AccountId = accounts(2);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000)
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        let (public_key, signed_order) = sign_order(&[7; 32], CheckoutPayload {
+            contract_id: accounts(0),
+            order_id: "order_1".to_owned(),
+            merchant_id: bob.clone(),
+            payer_id: alice.clone(),
+            token: None,
+            amount: U128(500),
+            expires_at: 1_000.into(),
+            nonce: 1.into(),
+            line_items: vec![]
+        });
+        contract.register_merchant(bob.clone(), Some(public_key));
+
+        PayOrder::new("order_1", 1).signed_order(signed_order).pay(&mut contract);
+
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.amount, 500);
+        assert_eq!(order.merchant_id, bob);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_INVALID_SIGNATURE")]
+    fn test_pay_signed_order_with_tampered_amount() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let bob: AccountId = accounts(2);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000)
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        let (public_key, mut signed_order) = sign_order(&[7; 32], CheckoutPayload {
+            contract_id: accounts(0),
+            order_id: "order_1".to_owned(),
+            merchant_id: bob.clone(),
+            payer_id: alice.clone(),
+            token: None,
+            amount: U128(500),
+            expires_at: 1_000.into(),
+            nonce: 1.into(),
+            line_items: vec![]
+        });
+        contract.register_merchant(bob, Some(public_key));
+
+        signed_order.payload.amount = U128(1);
+        PayOrder::new("order_1", 1).signed_order(signed_order).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_SIGNED_ORDER_MISMATCH")]
+    fn test_pay_signed_order_of_another_contract() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let bob: AccountId = accounts(2);
+        let mut contract = new_contract(&mut context, 1000);
+
+        // signed for another deployment of the payment contract
+        let (public_key, signed_order) = sign_order(&[7; 32], CheckoutPayload {
+            contract_id: accounts(5),
+            order_id: "order_1".to_owned(),
+            merchant_id: bob.clone(),
+            payer_id: alice,
+            token: None,
+            amount: U128(500),
+            expires_at: 1_000.into(),
+            nonce: 1.into(),
+            line_items: vec![]
+        });
+        contract.register_merchant(bob, Some(public_key));
+        PayOrder::new("order_1", 1).signed_order(signed_order).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_expired_nonces_pruned() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let bob: AccountId = accounts(2);
+        let mut contract = new_contract(&mut context, 1000);
+
+        let signed_order = |order_id: &str, nonce: u64, expires_at: u64| sign_order(&[7; 32], CheckoutPayload {
+            contract_id: accounts(0),
+            order_id: order_id.to_owned(),
+            merchant_id: bob.clone(),
+            payer_id: alice.clone(),
+            token: None,
+            amount: U128(500),
+            expires_at: expires_at.into(),
+            nonce: nonce.into(),
+            line_items: vec![]
+        });
+        let (public_key, order_1) = signed_order("order_1", 1, 1_000);
+        contract.register_merchant(bob.clone(), Some(public_key));
+        PayOrder::new("order_1", 1).signed_order(order_1).pay(&mut contract);
+        assert!(contract.used_nonces.contains(&(bob.clone(), 1)));
+
+        // nonce 1 is forgotten once its signed order expired
+        testing_env!(context.block_timestamp(2_000).build());
+        PayOrder::new("order_2", 1).signed_order(signed_order("order_2", 2, 3_000).1).pay(&mut contract);
+        assert!(!contract.used_nonces.contains(&(bob.clone(), 1)));
+        assert!(contract.used_nonces.contains(&(bob, 2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_SIGNED_ORDER_TTL_TOO_LONG")]
+    fn test_pay_signed_order_with_long_expiry() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let bob: AccountId = accounts(2);
+        let mut contract = new_contract(&mut context, 1000);
+
+        let (public_key, signed_order) = sign_order(&[7; 32], CheckoutPayload {
+            contract_id: accounts(0),
+            order_id: "order_1".to_owned(),
+            merchant_id: bob.clone(),
+            payer_id: alice,
+            token: None,
+            amount: U128(500),
+            expires_at: (MAX_SIGNED_ORDER_TTL + 1).into(),
+            nonce: 1.into(),
+            line_items: vec![]
+        });
+        contract.register_merchant(bob, Some(public_key));
+        PayOrder::new("order_1", 1).signed_order(signed_order).pay(&mut contract);
+    }
+
+    fn line_item(sku: &str, quantity: u32, unit_price: u128) -> LineItem {
+        LineItem {
+            sku: sku.to_owned(),
+            quantity,
+            unit_price: U128(unit_price),
+            tax: U128(0),
+            discount: U128(0),
+            refunded_quantity: 0
+        }
+    }
+
+    #[test]
+    fn test_refund_line_items() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        let line_items = vec![line_item("shirt", 2, 300), line_item("hat", 1, 400)];
+        PayOrder::new("order_1", 1000).line_items(line_items).pay(&mut contract);
+
+        contract.refund_line_items("order_1".to_owned(), vec![LineItemRefund { sku: "shirt".to_owned(), quantity: 1 }]);
+
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.refunded_amount, 300);
+        assert_eq!(order.line_items[0].refunded_quantity, 1);
+        assert!(!order.is_refund);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_TOO_MANY_LINE_ITEMS")]
+    fn test_pay_order_with_too_many_line_items() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        let line_items: Vec<LineItem> = (0..=MAX_LINE_ITEMS).map(|index| line_item(&format!("sku_{}", index), 1, 1)).collect();
+        PayOrder::new("order_1", MAX_LINE_ITEMS as Balance + 1).line_items(line_items).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_INVALID_SKU")]
+    fn test_pay_order_with_long_sku() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        let line_items = vec![line_item(&"s".repeat(MAX_SKU_LENGTH + 1), 1, 1000)];
+        PayOrder::new("order_1", 1000).line_items(line_items).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_LINE_ITEMS_TOTAL_MISMATCH")]
+    fn test_pay_order_with_wrong_line_items_total() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        let line_items = vec![line_item("shirt", 2, 300)];
+        PayOrder::new("order_1", 1000).line_items(line_items).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_OUT_OF_STOCK")]
+    fn test_pay_order_out_of_stock() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.add_product("shirt".to_owned(), vec![ProductPrice { token: None, price: U128(500) }], 1, true);
+
+        PayOrder::new("order_1", 500).line_items(vec![line_item("shirt", 1, 500)]).pay(&mut contract);
+        assert_eq!(contract.get_product(alice.clone(), "shirt".to_owned()).stock, 0);
+
+        PayOrder::new("order_2", 500).line_items(vec![line_item("shirt", 1, 500)]).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_PRODUCT_PRICE_MISMATCH")]
+    fn test_pay_order_with_discounted_product_line() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+        contract.add_product("book".to_owned(), vec![ProductPrice { token: None, price: U128(400) }], 1, true);
+
+        // the buyer cannot set the product line free
+        testing_env!(context.predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).build());
+        let line = LineItem { discount: U128(400), ..line_item("book", 1, 400) };
+        PayOrder::new("order_1", 0).line_items(vec![line]).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_release_expired_invoice_restores_stock() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.add_product("shirt".to_owned(), vec![], 5, true);
+        contract.create_invoice("order_1".to_owned(), U128(1_000), PriceUnit::Fiat, Some(vec![line_item("shirt", 2, 500)]));
+        assert_eq!(contract.get_product(alice.clone(), "shirt".to_owned()).stock, 3);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).block_timestamp(DEFAULT_RESERVATION_TTL + 1).build());
+        contract.release_invoice("order_1".to_owned());
+        assert_eq!(contract.get_product(alice, "shirt".to_owned()).stock, 5);
+    }
+
+    fn coupon(code: &str, discount: Discount, per_account_limit: Option<u32>) -> Coupon {
+        Coupon {
+            merchant_id: accounts(0),
+            code: code.to_owned(),
+            discount,
+            usage_limit: None,
+            per_account_limit,
+            starts_at: None,
+            ends_at: None,
+            allowed_tokens: vec![],
+            used_count: 0
+        }
+    }
+
+    #[test]
+    fn test_pay_order_with_coupon() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.set_coupon(coupon("SALE20", Discount::Percentage { basis_points: 2_000 }, None));
+        PayOrder::new("order_1", 1000).coupon_code("SALE20".to_owned()).pay(&mut contract);
+
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.amount, 800);
+        assert_eq!(order.discount, 200);
+        assert_eq!(contract.get_coupon(alice.clone(), "SALE20".to_owned()).used_count, 1);
+        assert_eq!(contract.get_coupon_usage(alice.clone(), "SALE20".to_owned(), alice), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_COUPON_ACCOUNT_LIMIT")]
+    fn test_pay_order_with_coupon_above_account_limit() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        let mut welcome = coupon("WELCOME", Discount::Fixed { amount: U128(100) }, Some(1));
+        welcome.allowed_tokens = vec![None];
+        contract.set_coupon(welcome);
+        PayOrder::new("order_1", 1000).coupon_code("WELCOME".to_owned()).pay(&mut contract);
+        PayOrder::new("order_2", 1000).coupon_code("WELCOME".to_owned()).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_loyalty_reward_clawed_back_on_refund() {
+        let mut context = get_context(false);
+        let vbi: AccountId = accounts(1);
+        let mut contract = new_contract(&mut context, 1_000_000_000);
+        accept_ft(&mut context, &mut contract, vbi.clone(), 24);
+        contract.set_loyalty_config(Some(LoyaltyConfig { reward_token_id: vbi, reward_basis_points: 500 }));
+        let line_items = vec![line_item("shirt", 2, 300), line_item("hat", 1, 400)];
+        contract.create_invoice("order_1".to_owned(), U128(1000), PriceUnit::Normalized, Some(line_items));
+        PayOrder::new("order_1", 0).pay(&mut contract);
+        assert_eq!(contract.get_order("order_1".to_owned()).reward_amount, 50_000_000);
+
+        // refunding 300 of 1000 normalized units claws back 30% of the reward
+        let items = vec![LineItemRefund { sku: "shirt".to_owned(), quantity: 1 }];
+        contract.refund_line_items("order_1".to_owned(), items.clone());
+        callback_context(&mut context, REFUND_CALLBACK_GAS, PromiseResult::Successful(vec![]));
+        contract.on_refund_line_items("order_1".to_owned(), items, U128(300_000_000));
+        assert_eq!(contract.get_order("order_1".to_owned()).reward_amount, 35_000_000);
+    }
+
+    #[test]
+    fn test_refund_callbacks_burn_reward_within_attached_gas() {
+        let mut context = get_context(false);
+        let vbi: AccountId = accounts(1);
+        let mut contract = new_contract(&mut context, 1_000_000_000);
+        accept_ft(&mut context, &mut contract, vbi.clone(), 24);
+        contract.set_loyalty_config(Some(LoyaltyConfig { reward_token_id: vbi, reward_basis_points: 500 }));
+        let line_items = vec![line_item("shirt", 2, 300), line_item("hat", 1, 400)];
+        contract.create_invoice("order_1".to_owned(), U128(1000), PriceUnit::Normalized, Some(line_items));
+        PayOrder::new("order_1", 0).pay(&mut contract);
+
+        contract.refund("order_1".to_owned());
+        callback_context(&mut context, REFUND_CALLBACK_GAS, PromiseResult::Successful(vec![]));
+        contract.pay_order_callbacks("order_1".to_owned());
+        assert_eq!(contract.get_order("order_1".to_owned()).reward_amount, 0);
+
+        // a batch callback gets the reward burn gas of each rewarded order
+        testing_env!(context.build());
+        contract.create_invoice("order_2".to_owned(), U128(1000), PriceUnit::Normalized, None);
+        PayOrder::new("order_2", 0).pay(&mut contract);
+        contract.refund_batch(vec!["order_2".to_owned()], None);
+        let order = contract.get_order("order_2".to_owned());
+        assert!(order.reward_amount > 0);
+        callback_context(&mut context, REFUND_BATCH_CALLBACK_GAS + refund_batch_order_gas(&order), PromiseResult::Successful(vec![]));
+        contract.on_refund_batch(vec![vec!["order_2".to_owned()]], vec![], None);
+        assert_eq!(contract.get_order("order_2".to_owned()).reward_amount, 0);
+    }
+
+    #[test]
+    fn test_ad_hoc_order_earns_no_reward() {
+        let mut context = get_context(false);
+        let vbi: AccountId = accounts(1);
+        let mut contract = new_contract(&mut context, 1000);
+        accept_ft(&mut context, &mut contract, vbi.clone(), 24);
+        contract.set_loyalty_config(Some(LoyaltyConfig { reward_token_id: vbi, reward_basis_points: 500 }));
+
+        // the payer prices ad-hoc orders, dust orders must not spend the reward storage deposit
+        PayOrder::new("order_1", 1).pay(&mut contract);
+        PayOrder::new("order_2", 999).pay(&mut contract);
+        assert_eq!(contract.get_order("order_1".to_owned()).reward_token_id, None);
+        assert_eq!(contract.get_order("order_2".to_owned()).reward_amount, 0);
+    }
+
+    #[test]
+    fn test_pay_order_with_gift_card() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let code_hash: near_sdk::json_types::Base58CryptoHash = env::sha256_array(b"GIFT-1234").into();
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        PayOrder::new("order_1", 1000).gift_card(GiftCardUse::Purchase { code_hash, holder_id: None }).pay(&mut contract);
+        assert_eq!(contract.get_gift_card_balance(code_hash), U128(1000));
+
+        // the card pays 1000 of 1500, the rest comes from the deposit
+        let gift_card = GiftCardUse::Redeem { code_hash };
+        PayOrder::new("order_2", 1500).gift_card(gift_card).pay(&mut contract);
+        let order = contract.get_order("order_2".to_owned());
+        assert_eq!(order.amount, 500);
+        assert_eq!(order.gift_card_payment.unwrap().amount, U128(1000));
+        assert_eq!(contract.get_gift_card_balance(code_hash), U128(0));
+
+        // refunds credit the card share back as store credit
+        contract.refund("order_2".to_owned());
+        assert_eq!(contract.get_gift_card_balance(code_hash), U128(1000));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_NOT_GIFT_CARD_HOLDER")]
+    fn test_redeem_gift_card_of_another_holder() {
+        let mut context = get_context(false);
+        let code_hash: near_sdk::json_types::Base58CryptoHash = env::sha256_array(b"GIFT-1234").into();
+        let mut contract = new_contract(&mut context, 1000);
+        PayOrder::new("order_1", 1000).gift_card(GiftCardUse::Purchase { code_hash, holder_id: None }).pay(&mut contract);
+        assert_eq!(contract.get_gift_card(code_hash).holder_id, accounts(0));
+
+        // the holder spends part of the card
+        testing_env!(context.attached_deposit(0).build());
+        PayOrder::new("order_2", 400).gift_card(GiftCardUse::Redeem { code_hash }).pay(&mut contract);
+        assert_eq!(contract.get_gift_card_balance(code_hash), U128(600));
+
+        // knowing the card does not let another account spend the rest
+        testing_env!(context.predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).build());
+        PayOrder::new("order_3", 600).gift_card(GiftCardUse::Redeem { code_hash }).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_transfer_gift_card() {
+        let mut context = get_context(false);
+        let code_hash: near_sdk::json_types::Base58CryptoHash = env::sha256_array(b"GIFT-1234").into();
+        let mut contract = new_contract(&mut context, 1000);
+        PayOrder::new("order_1", 1000).gift_card(GiftCardUse::Purchase { code_hash, holder_id: None }).pay(&mut contract);
+        contract.transfer_gift_card(code_hash, accounts(2));
+
+        testing_env!(context.predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).attached_deposit(0).build());
+        PayOrder::new("order_2", 1000).gift_card(GiftCardUse::Redeem { code_hash }).pay(&mut contract);
+        assert_eq!(contract.get_gift_card_balance(code_hash), U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_GIFT_CARD_NOT_REFUNDABLE")]
+    fn test_refund_gift_card_purchase() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let code_hash: near_sdk::json_types::Base58CryptoHash = env::sha256_array(b"GIFT-1234").into();
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        PayOrder::new("order_1", 1000).gift_card(GiftCardUse::Purchase { code_hash, holder_id: None }).pay(&mut contract);
+        contract.refund("order_1".to_owned());
+    }
+
+    #[test]
+    fn test_failed_campaign_refunds_in_batches() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000)
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        contract.create_campaign("launch".to_owned(), None, U128(3000), 100);
+        PayOrder::new("order_1", 1000).campaign_id("launch".to_owned()).pay(&mut contract);
+        PayOrder::new("order_2", 1000).campaign_id("launch".to_owned()).pay(&mut contract);
+        assert_eq!(contract.get_campaign("launch".to_owned()).raised, U128(2000));
+
+        testing_env!(context.block_timestamp(101).build());
+        contract.finalize_campaign("launch".to_owned());
+        assert_eq!(contract.get_campaign("launch".to_owned()).status, CampaignStatus::Failed);
+
+        assert_eq!(contract.refund_campaign("launch".to_owned(), 1), 1);
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+        assert!(!contract.get_order("order_2".to_owned()).is_refund);
+        assert_eq!(contract.refund_campaign("launch".to_owned(), 1), 0);
+        assert!(contract.get_order("order_2".to_owned()).is_refund);
+    }
+
+    #[test]
+    fn test_failed_campaign_refund_retried() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        contract.create_campaign("launch".to_owned(), None, U128(3000), 100);
+        PayOrder::new("order_1", 1000).campaign_id("launch".to_owned()).pay(&mut contract);
+        PayOrder::new("order_2", 1000).campaign_id("launch".to_owned()).pay(&mut contract);
+
+        testing_env!(context.block_timestamp(101).attached_deposit(0).build());
+        contract.finalize_campaign("launch".to_owned());
+        assert_eq!(contract.refund_campaign("launch".to_owned(), 1), 1);
+
+        // the transfer to the payer of order_1 fails, it is refunded again before order_2
+        callback_context(&mut context, REFUND_CALLBACK_GAS, PromiseResult::Failed);
+        contract.pay_order_callbacks("order_1".to_owned());
+        assert!(!contract.get_order("order_1".to_owned()).is_refund);
+        assert_eq!(contract.get_campaign("launch".to_owned()).failed_refunds, vec!["order_1".to_owned()]);
+
+        testing_env!(context.prepaid_gas(Gas(300_000_000_000_000)).build());
+        assert_eq!(contract.refund_campaign("launch".to_owned(), 1), 1);
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+        assert!(!contract.get_order("order_2".to_owned()).is_refund);
+        assert_eq!(contract.refund_campaign("launch".to_owned(), 1), 0);
+        assert!(contract.get_order("order_2".to_owned()).is_refund);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_CAMPAIGN_ORDER_NOT_REFUNDABLE")]
+    fn test_refund_succeeded_campaign_order() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000)
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        contract.create_campaign("launch".to_owned(), None, U128(1000), 100);
+        PayOrder::new("order_1", 1000).campaign_id("launch".to_owned()).pay(&mut contract);
+
+        testing_env!(context.block_timestamp(101).build());
+        contract.finalize_campaign("launch".to_owned());
+        let campaign = contract.get_campaign("launch".to_owned());
+        assert_eq!(campaign.status, CampaignStatus::Succeeded);
+        assert!(campaign.is_settled);
+
+        contract.refund("order_1".to_owned());
+    }
+
+    fn installment_plan(contract: &mut EcommerceContract, payer_id: AccountId) -> InstallmentPlan {
+        contract.create_installment_plan(
+            "order_1".to_owned(),
+            payer_id,
+            None,
+            U128(400),
+            vec![ScheduleItem { due_at: 100, amount: U128(300) }, ScheduleItem { due_at: 200, amount: U128(300) }],
+            LateFeeRule { grace_period: 10, fee: U128(50) },
+            50
+        )
+    }
+
+    #[test]
+    fn test_pay_installment_plan() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(400)
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        installment_plan(&mut contract, alice);
+
+        PayOrder::new("order_1", 0).pay(&mut contract);
+        assert_eq!(contract.get_installment_plan("order_1".to_owned()).status, PlanStatus::Active);
+        assert!(!contract.get_order("order_1".to_owned()).is_completed);
+
+        testing_env!(context.block_timestamp(90).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+
+        // paid after the grace period, the late fee is added
+        testing_env!(context.block_timestamp(220).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+
+        let plan = contract.get_installment_plan("order_1".to_owned());
+        assert_eq!(plan.status, PlanStatus::Completed);
+        assert_eq!(plan.installments[1].late_fee, U128(50));
+        let order = contract.get_order("order_1".to_owned());
+        assert!(order.is_completed);
+        assert_eq!(order.amount, 1050);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_PLAN_DEFAULTED")]
+    fn test_pay_defaulted_installment_plan() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(400)
+        .block_timestamp(0);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        installment_plan(&mut contract, alice);
+        PayOrder::new("order_1", 0).pay(&mut contract);
+
+        testing_env!(context.block_timestamp(151).build());
+        assert_eq!(contract.check_installment_plan("order_1".to_owned()), PlanStatus::Defaulted);
+        PayOrder::new("order_1", 0).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_refund_defaulted_installment_plan() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let bob: AccountId = accounts(2);
+        let mut contract = new_contract(&mut context, 0);
+        installment_plan(&mut contract, bob.clone());
+        testing_env!(context.signer_account_id(bob).attached_deposit(400).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+
+        // the default closes the order at the down payment, the merchant can refund it
+        testing_env!(context.block_timestamp(151).attached_deposit(0).build());
+        assert_eq!(contract.check_installment_plan("order_1".to_owned()), PlanStatus::Defaulted);
+        let order = contract.get_order("order_1".to_owned());
+        assert!(order.is_completed);
+        assert_eq!(order.amount, 400);
+        assert_eq!(contract.get_merchant_balance(alice.clone(), None).pending, U128(400));
+
+        contract.refund("order_1".to_owned());
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+        assert_eq!(contract.get_merchant_balance(alice, None).pending, U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_DAILY_VOLUME_LIMIT")]
+    fn test_pay_installments_above_daily_volume_cap() {
+        let mut context = get_context(false);
+        let bob: AccountId = accounts(2);
+        let mut contract = new_contract(&mut context, 0);
+        installment_plan(&mut contract, bob.clone());
+        contract.set_risk_limits(None, Some(RiskLimits { max_order_amount: None, daily_volume_cap: Some(U128(600)) }));
+
+        // installments count toward the daily volume of their payer
+        testing_env!(context.signer_account_id(bob).attached_deposit(400).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+        testing_env!(context.attached_deposit(300).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_ACCOUNT_DENIED")]
+    fn test_pay_installment_with_denied_account() {
+        let mut context = get_context(false);
+        let bob: AccountId = accounts(2);
+        let mut contract = new_contract(&mut context, 0);
+        installment_plan(&mut contract, bob.clone());
+        contract.deny_account(bob.clone());
+
+        testing_env!(context.signer_account_id(bob).attached_deposit(400).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_pay_orders() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let bob: AccountId = accounts(2);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000 + 500_000_000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.register_merchant(bob.clone(), None);
+        testing_env!(context.predecessor_account_id(bob.clone()).build());
+        contract.create_invoice("order_2".to_owned(), U128(500), PriceUnit::Normalized, None);
+
+        testing_env!(context.predecessor_account_id(alice).build());
+        let change = contract.pay_orders(vec![("order_1".to_owned(), U128(1000)), ("order_2".to_owned(), U128(0))]);
+        match change {
+            PromiseOrValue::Value(change) => assert_eq!(change, U128(0)),
+            _ => panic!("ERROR_NOT_VALUE")
+        }
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!((order.amount, order.received_amount), (1000, 1000));
+        // 500 normalized units of NEAR
+        let order = contract.get_order("order_2".to_owned());
+        assert_eq!((order.amount, order.received_amount), (500_000_000, 500_000_000));
+        assert_eq!(order.merchant_id, bob);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_DEPOSIT_NOT_ENOUGH")]
+    fn test_pay_orders_with_lack_balance() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1500);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        contract.pay_orders(vec![("order_1".to_owned(), U128(1000)), ("order_2".to_owned(), U128(1000))]);
+    }
+
+    #[test]
+    fn test_refund_batch() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        PayOrder::new("order_2", 1000).pay(&mut contract);
+
+        let order_ids = vec!["order_1".to_owned(), "order_2".to_owned(), "order_3".to_owned()];
+        contract.refund_batch(order_ids, None);
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+        assert!(contract.get_order("order_2".to_owned()).is_refund);
+
+        // both orders of the payer were grouped into one transfer, which failed
+        callback_context(&mut context, REFUND_BATCH_CALLBACK_GAS + REFUND_BATCH_ORDER_GAS, PromiseResult::Failed);
+        let outcomes = vec![RefundOutcome { order_id: "order_3".to_owned(), status: RefundStatus::Skipped }];
+        let report = contract.on_refund_batch(vec![vec!["order_1".to_owned(), "order_2".to_owned()]], outcomes, None);
+        let statuses: Vec<RefundStatus> = report.outcomes.iter().map(|outcome| outcome.status).collect();
+        assert_eq!(statuses, vec![RefundStatus::Skipped, RefundStatus::Failed, RefundStatus::Failed]);
+        assert!(!contract.get_order("order_1".to_owned()).is_refund);
+    }
+
+    #[test]
+    fn test_refund_batch_stops_before_gas_runs_out() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        PayOrder::new("order_2", 1000).pay(&mut contract);
+
+        // enough gas for the callback of a single order
+        testing_env!(context.prepaid_gas(Gas(28_000_000_000_000)).build());
+        contract.refund_batch(vec!["order_1".to_owned(), "order_2".to_owned()], None);
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+        assert!(!contract.get_order("order_2".to_owned()).is_refund);
+    }
+
+    #[test]
+    fn test_pay_order_with_tip() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1200);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.set_tip_recipient(Some(accounts(3)));
+        let change = PayOrder::new("order_1", 1000).tip(150).pay(&mut contract);
+        match change {
+            PromiseOrValue::Value(change) => assert_eq!(change, U128(50)),
+            _ => panic!("ERROR_NOT_VALUE")
+        }
+
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.amount, 1000);
+        assert_eq!(order.tip, 150);
+        assert_eq!(contract.get_merchant(alice).tip_recipient, Some(accounts(3)));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_DEPOSIT_NOT_ENOUGH")]
+    fn test_pay_order_with_tip_above_deposit() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        PayOrder::new("order_1", 1000).tip(1).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_receipt_minted_and_transferred() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let bob: AccountId = accounts(2);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        let token = contract.nft_token("order_1".to_owned()).unwrap();
+        assert_eq!(token.owner_id, alice);
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.nft_transfer(bob.clone(), "order_1".to_owned(), None, None);
+        assert_eq!(contract.nft_token("order_1".to_owned()).unwrap().owner_id, bob);
+        assert_eq!(contract.nft_supply_for_owner(alice), U128(0));
+        assert_eq!(contract.nft_tokens_for_owner(bob, None, None).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_RECEIPT_VOID")]
+    fn test_refunded_receipt_is_void() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        contract.refund("order_1".to_owned());
+
+        callback_context(context.attached_deposit(1), REFUND_CALLBACK_GAS, PromiseResult::Successful(vec![]));
+        contract.pay_order_callbacks("order_1".to_owned());
+        contract.nft_transfer(accounts(2), "order_1".to_owned(), None, None);
+    }
+
+    #[test]
+    fn test_pay_order_with_nft() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let nft_contract_id: AccountId = accounts(3);
+
+        testing_env!(context.predecessor_account_id(alice.clone()).build());
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.add_accepted_nft(nft_contract_id.clone(), None, U128(1000));
+
+        testing_env!(context.predecessor_account_id(nft_contract_id.clone()).build());
+        let msg = r#"{"order_id": "order_1", "order_amount": "800"}"#.to_owned();
+        let keep = contract.nft_on_transfer(alice.clone(), alice.clone(), "nft_1".to_owned(), msg);
+        assert!(matches!(keep, PromiseOrValue::Value(false)));
+
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.amount, 800);
+        assert_eq!(order.payment_method, PaymentMethod::NonFungibleToken);
+        assert_eq!(order.nft_payment.unwrap().nft_contract_id, nft_contract_id);
+
+        // the NFT is returned, there is no fungible refund
+        testing_env!(context.predecessor_account_id(alice.clone()).build());
+        contract.refund("order_1".to_owned());
+        callback_context(&mut context, REFUND_CALLBACK_GAS, PromiseResult::Successful(vec![]));
+        contract.pay_order_callbacks("order_1".to_owned());
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_NFT_NOT_ACCEPTED")]
+    fn test_pay_order_with_not_accepted_nft() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        testing_env!(context.predecessor_account_id(alice.clone()).build());
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        let msg = r#"{"order_id": "order_1", "order_amount": "800"}"#.to_owned();
+        contract.nft_on_transfer(alice.clone(), alice, "nft_1".to_owned(), msg);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_NFT_ALREADY_SETTLED")]
+    fn test_nft_payment_settled_on_confirmed_delivery() {
+        let mut context = get_context(false);
+        let bob: AccountId = accounts(1);
+        let mut contract = new_contract(&mut context, 0);
+        contract.add_accepted_nft(accounts(3), None, U128(1000));
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        let msg = r#"{"order_id": "order_1", "order_amount": "800"}"#.to_owned();
+        contract.nft_on_transfer(bob.clone(), bob, "nft_1".to_owned(), msg);
+        ship_order(&mut context, &mut contract, "order_1");
+
+        // confirming delivery transfers the NFT to the merchant
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.confirm_delivery("order_1".to_owned());
+        assert!(contract.get_order("order_1".to_owned()).nft_payment.unwrap().is_settled);
+
+        callback_context(&mut context, NFT_SETTLED_CALLBACK_GAS, PromiseResult::Successful(vec![]));
+        contract.on_nft_settled("order_1".to_owned());
+        assert!(contract.get_order("order_1".to_owned()).nft_payment.unwrap().is_settled);
+
+        // the merchant owns the NFT, it can no longer go back to the payer
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.refund("order_1".to_owned());
+    }
+
+    #[test]
+    fn test_nft_payment_settled_after_hold_period() {
+        let mut context = get_context(false);
+        let bob: AccountId = accounts(1);
+        let mut contract = new_contract(&mut context, 0);
+        contract.add_accepted_nft(accounts(3), None, U128(1000));
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        let msg = r#"{"order_id": "order_1", "order_amount": "800"}"#.to_owned();
+        contract.nft_on_transfer(bob.clone(), bob, "nft_1".to_owned(), msg);
+        ship_order(&mut context, &mut contract, "order_1");
+
+        // anyone settles once the hold period is over, a failed transfer can be settled again
+        testing_env!(context.predecessor_account_id(accounts(2)).block_timestamp(DEFAULT_HOLD_PERIOD).build());
+        contract.settle_nft_payment("order_1".to_owned());
+        callback_context(&mut context, NFT_SETTLED_CALLBACK_GAS, PromiseResult::Failed);
+        contract.on_nft_settled("order_1".to_owned());
+        assert!(!contract.get_order("order_1".to_owned()).nft_payment.unwrap().is_settled);
+
+        testing_env!(context.build());
+        contract.settle_nft_payment("order_1".to_owned());
+        assert!(contract.get_order("order_1".to_owned()).nft_payment.unwrap().is_settled);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_NFT_PAYMENT_HELD")]
+    fn test_nft_payment_not_settled_during_hold_period() {
+        let mut context = get_context(false);
+        let bob: AccountId = accounts(1);
+        let mut contract = new_contract(&mut context, 0);
+        contract.add_accepted_nft(accounts(3), None, U128(1000));
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        let msg = r#"{"order_id": "order_1", "order_amount": "800"}"#.to_owned();
+        contract.nft_on_transfer(bob.clone(), bob, "nft_1".to_owned(), msg);
+        ship_order(&mut context, &mut contract, "order_1");
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.settle_nft_payment("order_1".to_owned());
+    }
+
+    fn payment_hook(on_failure: HookFailure) -> PaymentHook {
+        PaymentHook {
+            contract_id: accounts(3),
+            method_name: "on_order_paid".to_owned(),
+            gas: Gas(10_000_000_000_000),
+            on_failure
+        }
+    }
+
+    #[test]
+    fn test_failed_payment_hook_refunds_order() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        contract.set_payment_hook(Some(payment_hook(HookFailure::Refund)));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        callback_context(&mut context, PAYMENT_HOOK_CALLBACK_GAS, PromiseResult::Failed);
+        contract.on_payment_hook("order_1".to_owned(), HookFailure::Refund);
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+    }
+
+    #[test]
+    fn test_failed_payment_hook_keeps_order() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        contract.set_payment_hook(Some(payment_hook(HookFailure::Keep)));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        callback_context(&mut context, PAYMENT_HOOK_CALLBACK_GAS, PromiseResult::Failed);
+        contract.on_payment_hook("order_1".to_owned(), HookFailure::Keep);
+        let order = contract.get_order("order_1".to_owned());
+        assert!(order.is_completed && !order.is_refund);
+    }
+
+    #[test]
+    fn test_failed_payment_hook_refund_requires_multisig() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1_000_000_000);
+        contract.set_multisig_config(Some(multisig_config(1)));
+        contract.set_payment_hook(Some(payment_hook(HookFailure::Refund)));
+        PayOrder::new("order_1", 1_000_000_000).pay(&mut contract);
+
+        callback_context(&mut context, PAYMENT_HOOK_CALLBACK_GAS, PromiseResult::Failed);
+        contract.on_payment_hook("order_1".to_owned(), HookFailure::Refund);
+        assert!(!contract.get_order("order_1".to_owned()).is_refund);
+        assert!(matches!(contract.get_request(0).action, MultisigAction::Refund { .. }));
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.confirm_request(0);
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+    }
+
+    #[test]
+    fn test_merchant_balance_released_after_hold_period() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        let balance = contract.get_merchant_balance(alice.clone(), None);
+        assert_eq!(balance.pending, U128(1000));
+        assert_eq!(balance.available, U128(0));
+        let release = contract.get_pending_release(alice.clone(), None, "order_1".to_owned()).unwrap();
+        assert_eq!(release.release_at, DEFAULT_HOLD_PERIOD);
+
+        testing_env!(context.block_timestamp(DEFAULT_HOLD_PERIOD).attached_deposit(0).build());
+        let balance = contract.get_merchant_balance(alice.clone(), None);
+        assert_eq!(balance.pending, U128(0));
+        assert_eq!(balance.available, U128(1000));
+
+        // the matured release is dropped from storage on the next write
+        contract.withdraw(None, U128(600));
+        let balance = contract.get_merchant_balance(alice.clone(), None);
+        assert_eq!(balance.available, U128(400));
+        assert_eq!((balance.release_head, balance.release_tail), (1, 1));
+        assert!(contract.get_pending_release(alice, None, "order_1".to_owned()).is_none());
+    }
+
+    #[test]
+    fn test_merchant_balance_released_in_batches() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let mut contract = new_contract(&mut context, 10);
+        for index in 0..60 {
+            PayOrder::new(&format!("order_{}", index), 10).pay(&mut contract);
+        }
+
+        // each call matures at most MAX_RELEASES_PER_CALL releases
+        testing_env!(context.block_timestamp(DEFAULT_HOLD_PERIOD).attached_deposit(0).build());
+        assert_eq!(contract.get_merchant_balance(alice.clone(), None).available, U128(500));
+        contract.withdraw(None, U128(500));
+        let balance = contract.get_merchant_balance(alice.clone(), None);
+        assert_eq!((balance.available, balance.pending), (U128(100), U128(0)));
+        assert_eq!(contract.get_pending_releases(alice, None, 0, 100).len(), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_MERCHANT_BALANCE_NOT_ENOUGH")]
+    fn test_withdraw_pending_balance() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        PayOrder::new("order_2", 1000).pay(&mut contract);
+
+        // the refund draws from the pending balance of its order
+        contract.refund("order_1".to_owned());
+        assert_eq!(contract.get_merchant_balance(alice.clone(), None).pending, U128(1000));
+        let releases = contract.get_pending_releases(alice, None, 0, 10);
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].order_id, "order_2".to_owned());
+
+        contract.withdraw(None, U128(1000));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_ACCOUNT_DENIED")]
+    fn test_pay_order_with_denied_account() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let bob: AccountId = accounts(2);
+        let ft_contract_id: AccountId = accounts(1);
+
+        testing_env!(context.predecessor_account_id(alice.clone()).build());
+        let mut contract = EcommerceContract::new(alice, ft_contract_id.clone());
+        contract.deny_account(bob.clone());
+
+        // the transferred tokens are returned by the failed ft_on_transfer
+        testing_env!(context.predecessor_account_id(ft_contract_id).build());
+        let msg = r#"{"order_id": "order_1", "order_amount": "1000"}"#.to_owned();
+        contract.ft_on_transfer(bob, U128(1000), msg);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_VELOCITY_LIMIT")]
+    fn test_pay_orders_above_velocity_limit() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        contract.set_velocity_limit(Some(VelocityLimit { max_orders: 2, period: 60_000_000_000 }));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        testing_env!(context.block_timestamp(30_000_000_000).build());
+        PayOrder::new("order_2", 1000).pay(&mut contract);
+
+        // the first order is out of the period
+        testing_env!(context.block_timestamp(60_000_000_000).build());
+        PayOrder::new("order_3", 1000).pay(&mut contract);
+        assert_eq!(contract.get_payer_activity(alice).unwrap().recent_orders.len(), 2);
+        PayOrder::new("order_4", 1000).pay(&mut contract);
+    }
+
+    /*
+     * Merchant alice sells in DE, at 25% by default and 10% for the book product
+     */
+    fn set_up_tax(contract: &mut EcommerceContract) {
+        contract.set_merchant_jurisdiction(accounts(0), Some("DE".to_owned()));
+        contract.set_tax_rate("DE".to_owned(), DEFAULT_TAX_CLASS.to_owned(), Some(2500));
+        contract.set_tax_rate("DE".to_owned(), "books".to_owned(), Some(1000));
+        contract.add_product("book".to_owned(), vec![ProductPrice { token: None, price: U128(400) }], 10, true);
+        contract.set_product_tax_class("book".to_owned(), Some("books".to_owned()));
+    }
+
+    #[test]
+    fn test_pay_order_with_tax() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        set_up_tax(&mut contract);
+        let line_items = vec![line_item("shirt", 2, 300), line_item("book", 1, 400)];
+        PayOrder::new("order_1", 1000).line_items(line_items).pay(&mut contract);
+
+        // 600 * 25 / 125 + 400 * 10 / 110
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.tax, 156);
+        assert_eq!(order.tax_jurisdiction, Some("DE".to_owned()));
+        assert_eq!(contract.get_tax_balance("DE".to_owned(), None), U128(156));
+        assert_eq!(contract.get_merchant_balance(accounts(0), None).pending, U128(844));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_LINE_TAX_NOT_ALLOWED")]
+    fn test_pay_order_with_line_tax() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        let line_items = vec![LineItem { tax: U128(100), ..line_item("shirt", 3, 300) }];
+        PayOrder::new("order_1", 1000).line_items(line_items).pay(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_LINE_TAX_NOT_ALLOWED")]
+    fn test_pay_invoice_with_line_tax_in_jurisdiction() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+        set_up_tax(&mut contract);
+        let line_items = vec![LineItem { tax: U128(100), ..line_item("shirt", 3, 300) }];
+        contract.create_invoice("order_1".to_owned(), U128(1000), PriceUnit::Normalized, Some(line_items));
+
+        testing_env!(context.attached_deposit(1_000_000_000).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+    }
+
+    #[test]
+    fn test_refund_line_items_reverses_tax() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        set_up_tax(&mut contract);
+        let line_items = vec![line_item("shirt", 2, 300), line_item("book", 1, 400)];
+        PayOrder::new("order_1", 1000).line_items(line_items).pay(&mut contract);
+
+        contract.refund_line_items("order_1".to_owned(), vec![LineItemRefund { sku: "shirt".to_owned(), quantity: 1 }]);
+
+        // 156 * 300 / 1000 of tax is refunded
+        assert_eq!(contract.get_tax_balance("DE".to_owned(), None), U128(110));
+        assert_eq!(contract.get_merchant_balance(accounts(0), None).pending, U128(590));
+    }
+
+    #[test]
+    fn test_confirm_delivery_releases_funds() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        ship_order(&mut context, &mut contract, "order_1");
+        assert_eq!(contract.get_merchant_balance(alice.clone(), None).available, U128(0));
+
+        contract.confirm_delivery("order_1".to_owned());
+        let fulfilment = contract.get_fulfilment("order_1".to_owned());
+        assert_eq!(fulfilment.status, Some(FulfilmentStatus::Delivered));
+        assert_eq!(fulfilment.updates.len(), 3);
+        assert!(fulfilment.delivery_confirmed_at.is_some());
+        assert_eq!(contract.get_merchant_balance(alice, None).available, U128(1000));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_INVALID_FULFILMENT_STATUS")]
+    fn test_deliver_order_not_shipped() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        contract.update_fulfilment("order_1".to_owned(), FulfilmentStatus::Processing, None);
+        contract.update_fulfilment("order_1".to_owned(), FulfilmentStatus::Delivered, None);
+    }
+
+    #[test]
+    fn test_return_with_restocking_fee() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice.clone(), accounts(1));
+        PayOrder::new("order_1", 1000).pay(&mut contract);
+        contract.request_return("order_1".to_owned(), "Wrong size".to_owned(), None);
+        contract.approve_return("order_1".to_owned(), Some(U128(100)));
+        contract.receive_return("order_1".to_owned());
+        assert_eq!(contract.get_return("order_1".to_owned()).status, ReturnStatus::Received);
+
+        callback_context(&mut context, REFUND_CALLBACK_GAS, PromiseResult::Successful(vec![]));
+        contract.on_refund_return("order_1".to_owned());
+
+        let return_request = contract.get_return("order_1".to_owned());
+        assert_eq!(return_request.status, ReturnStatus::Refunded);
+        assert_eq!(return_request.refund_amount, U128(1000));
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+        // the merchant keeps the restocking fee
+        assert_eq!(contract.get_merchant_balance(alice, None).pending, U128(100));
+    }
+
+    #[test]
+    fn test_receive_return_requires_multisig() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1_000_000_000);
+        PayOrder::new("order_1", 1_000_000_000).pay(&mut contract);
+        contract.request_return("order_1".to_owned(), "Wrong size".to_owned(), None);
+        contract.approve_return("order_1".to_owned(), Some(U128(100_000_000)));
+        contract.set_multisig_config(Some(multisig_config(1)));
+
+        contract.receive_return("order_1".to_owned());
+        assert_eq!(contract.get_return("order_1".to_owned()).status, ReturnStatus::Approved);
+        assert!(matches!(contract.get_request(0).action, MultisigAction::ReceiveReturn { .. }));
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.confirm_request(0);
+        let return_request = contract.get_return("order_1".to_owned());
+        assert_eq!(return_request.status, ReturnStatus::Received);
+        assert_eq!(return_request.refund_amount, U128(1_000_000_000));
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut context = get_context(false);
+        testing_env!(context.attached_deposit(1000).build());
+        let mut orders = LookupMap::new(StorageKey::OrderKey);
+        orders.insert(&"order_1".to_owned(), &migration::LegacyOrder {
+            order_id: "order_1".to_owned(),
+            payer_id: accounts(0),
+            payment_method: PaymentMethod::Near,
+            amount: 1000,
+            received_amount: 1000,
+            is_completed: true,
+            is_refund: false,
+            created_at: 0
+        });
+        env::state_write(&migration::LegacyEcommerceContract {
+            owner_id: accounts(0),
+            ft_contract_id: accounts(1),
+            orders
+        });
+
+        let mut contract = EcommerceContract::migrate();
+        assert_eq!(contract.migrate_orders(vec!["order_1".to_owned(), "order_2".to_owned()]), 1);
+        assert_eq!(contract.migrate_orders(vec!["order_1".to_owned()]), 0);
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.merchant_id, accounts(0));
+        assert_eq!(order.amount, 1000);
+        assert_eq!(contract.get_merchant_balance(accounts(0), None).available, U128(1000));
+
+        contract.refund("order_1".to_owned());
+        assert!(contract.get_order("order_1".to_owned()).is_refund);
+        assert_eq!(contract.get_merchant_balance(accounts(0), None).available, U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_INVALID_RETURN_STATUS")]
+    fn test_receive_return_not_approved() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+
+        context.predecessor_account_id(alice.clone())
+        .signer_account_id(alice.clone())
+        .attached_deposit(1000);
+
+        testing_env!(context.build());
+
+        let mut contract = EcommerceContract::new(alice, accounts(1));
+        let line_items = vec![line_item("shirt", 2, 300), line_item("hat", 1, 400)];
+        PayOrder::new("order_1", 1000).line_items(line_items).pay(&mut contract);
+        let items = vec![LineItemRefund { sku: "hat".to_owned(), quantity: 1 }];
+        contract.request_return("order_1".to_owned(), "Damaged".to_owned(), Some(items));
+        contract.receive_return("order_1".to_owned());
+    }
+
+    #[test]
+    fn test_post_review() {
+        let mut context = get_context(false);
+        let alice: AccountId = accounts(0);
+        let mut contract = new_contract(&mut context, 0);
+        contract.add_product("hat".to_owned(), vec![ProductPrice { token: None, price: U128(400) }], 10, true);
+        let line_items = vec![line_item("shirt", 2, 300), line_item("hat", 1, 400)];
+        contract.create_invoice("order_1".to_owned(), U128(1000), PriceUnit::Normalized, Some(line_items));
+        contract.create_invoice("order_2".to_owned(), U128(1000), PriceUnit::Normalized, None);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).attached_deposit(1_000_000_000).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+        PayOrder::new("order_2", 0).pay(&mut contract);
+        deliver_order(&mut context, &mut contract, "order_1", accounts(2));
+        deliver_order(&mut context, &mut contract, "order_2", accounts(2));
+
+        contract.post_review("order_1".to_owned(), 5, [1; 32].into());
+        contract.post_review("order_2".to_owned(), 2, [2; 32].into());
+        testing_env!(context.predecessor_account_id(alice.clone()).build());
+        contract.reply_to_review("order_2".to_owned(), [3; 32].into());
+
+        let merchant_rating = contract.get_merchant_rating(alice.clone());
+        assert_eq!((merchant_rating.num_reviews, merchant_rating.rating_total), (2, 7));
+        assert_eq!(contract.get_product_rating(alice.clone(), "hat".to_owned()).rating_total, 5);
+        // only products of the merchant are rated
+        assert_eq!(contract.get_product_rating(alice, "shirt".to_owned()).num_reviews, 0);
+        assert!(contract.get_review("order_2".to_owned()).reply.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_ORDER_NOT_REVIEWABLE")]
+    fn test_review_refunded_order() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+        contract.create_invoice("order_1".to_owned(), U128(1000), PriceUnit::Normalized, None);
+        testing_env!(context.predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).attached_deposit(1_000_000_000).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+        deliver_order(&mut context, &mut contract, "order_1", accounts(2));
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.refund("order_1".to_owned());
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.post_review("order_1".to_owned(), 1, [1; 32].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_ORDER_NOT_REVIEWABLE")]
+    fn test_review_ad_hoc_order() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+
+        // the payer sets the price of ad-hoc orders, even to 0
+        testing_env!(context.predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+        deliver_order(&mut context, &mut contract, "order_1", accounts(2));
+
+        contract.post_review("order_1".to_owned(), 5, [1; 32].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERROR_DELIVERY_NOT_CONFIRMED")]
+    fn test_review_undelivered_order() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+        contract.create_invoice("order_1".to_owned(), U128(1000), PriceUnit::Normalized, None);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).attached_deposit(1_000_000_000).build());
+        PayOrder::new("order_1", 0).pay(&mut contract);
+        contract.post_review("order_1".to_owned(), 5, [1; 32].into());
+    }
+
+    #[test]
+    fn test_pay_order_with_referrer() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+        contract.set_commission_rate(500);
+        contract.create_invoice("order_1".to_owned(), U128(1000), PriceUnit::Normalized, None);
+        testing_env!(context.predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).attached_deposit(1_000_000_000).build());
+        PayOrder::new("order_1", 0).referrer(accounts(3)).pay(&mut contract);
+        let order = contract.get_order("order_1".to_owned());
+        assert_eq!(order.referrer_id, Some(accounts(3)));
+        assert_eq!(order.commission, 50_000_000);
+        assert_eq!(contract.get_merchant_balance(accounts(0), None).pending, U128(950_000_000));
+        assert_eq!(contract.get_merchant_balance(accounts(3), None).pending, U128(50_000_000));
+
+        // the commission is withdrawable by the referrer after the hold period
+        testing_env!(context.predecessor_account_id(accounts(3)).block_timestamp(DEFAULT_HOLD_PERIOD).attached_deposit(0).build());
+        contract.withdraw(None, U128(50_000_000));
+        assert_eq!(contract.get_merchant_balance(accounts(3), None).available, U128(0));
+    }
+
+    #[test]
+    fn test_refund_reverses_commission() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 0);
+        contract.set_commission_rate(500);
+        contract.create_invoice("order_1".to_owned(), U128(1000), PriceUnit::Normalized, None);
+        testing_env!(context.predecessor_account_id(accounts(2)).signer_account_id(accounts(2)).attached_deposit(1_000_000_000).build());
+        PayOrder::new("order_1", 0).referrer(accounts(3)).pay(&mut contract);
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        contract.refund("order_1".to_owned());
+        assert_eq!(contract.get_merchant_balance(accounts(0), None).pending, U128(0));
+        assert_eq!(contract.get_merchant_balance(accounts(3), None).pending, U128(0));
+        assert!(contract.get_pending_release(accounts(3), None, "order_1".to_owned()).is_none());
+    }
+
+    #[test]
+    fn test_ad_hoc_order_pays_no_commission() {
+        let mut context = get_context(false);
+        let mut contract = new_contract(&mut context, 1000);
+        contract.set_commission_rate(500);
+
+        // the payer prices ad-hoc orders, a referrer of their own takes no share of the merchant funds
+        testing_env!(context.signer_account_id(accounts(2)).build());
+        PayOrder::new("order_1", 1000).referrer(accounts(3)).pay(&mut contract);
+        assert_eq!(contract.get_order("order_1".to_owned()).commission, 0);
+        assert_eq!(contract.get_merchant_balance(accounts(0), None).pending, U128(1000));
+        assert_eq!(contract.get_merchant_balance(accounts(3), None).pending, U128(0));
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+
+use near_sdk::{Balance, PromiseOrValue, PromiseResult, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+// Orders, invoices and signed orders store their line items, bounded to keep storage small
+pub const MAX_LINE_ITEMS: usize = 50;
+pub const MAX_SKU_LENGTH: usize = 64;
+
+/**
+ * Cart line, amounts are in the unit of the order price (token units, fiat cents or normalized units)
+ * - line total = quantity * unit_price + tax - discount
+ * - tax is only set by merchants without a jurisdiction, see set_merchant_jurisdiction
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LineItem {
+    pub sku: String,
+    pub quantity: u32,
+    pub unit_price: U128,
+    pub tax: U128,
+    pub discount: U128,
+    #[serde(default)]
+    pub refunded_quantity: u32
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LineItemRefund {
+    pub sku: String,
+    pub quantity: u32
+}
+
+impl LineItem {
+    pub fn total(&self) -> Balance {
+        (self.unit_price.0 * self.quantity as Balance + self.tax.0)
+            .checked_sub(self.discount.0)
+            .expect("ERROR_DISCOUNT_TOO_HIGH")
+    }
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Refund part of an order by line items
+     * - The refunded amount is the share of the line items in the order total
+     * - Refunds above the multisig threshold only create a pending request
+     */
+    pub fn refund_line_items(&mut self, order_id: OrderId, items: Vec<LineItemRefund>) -> PromiseOrValue<U128> {
+        self.assert_owner();
+
+        let order = self.get_order(order_id.clone());
+        assert!(order.is_completed && !order.is_refund, "ERROR_ORDER_NOT_REFUNDABLE");
+        assert!(order.issued_gift_card.is_none(), "ERROR_GIFT_CARD_NOT_REFUNDABLE");
+        assert!(order.nft_payment.is_none(), "ERROR_NFT_ORDER_NOT_SPLITTABLE");
+        self.assert_campaign_refundable(&order);
+
+        if self.requires_multisig(&order.token_id, line_items_refund_amount(&order, &items)) {
+            self.internal_add_request(MultisigAction::RefundLineItems { order_id, items });
+            return PromiseOrValue::Value(U128(0));
+        }
+
+        self.internal_refund_line_items(order_id, items)
+    }
+}
+
+impl EcommerceContract {
+    pub(crate) fn internal_on_refund_line_items(&mut self, order_id: OrderId, items: Vec<LineItemRefund>, amount: U128) -> U128 {
+        assert_eq!(env::promise_results_count(), 1, "ERROR_TOO_MANY_RESULTS");
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_value) => {
+                self.internal_on_line_items_refunded(order_id, &items, amount.0);
+                U128(0)
+            },
+            PromiseResult::Failed => {
+                // revert refunded quantities
+                let mut order = self.get_order(order_id.clone());
+                for refund in items.iter() {
+                    let line = order.line_items.iter_mut().find(|line| line.sku == refund.sku).unwrap();
+                    line.refunded_quantity -= refund.quantity;
+                }
+                order.refunded_amount -= amount.0;
+                self.orders.insert(&order_id, &order);
+                self.internal_credit_merchant(&order, amount.0);
+                amount
+            }
+        }
+    }
+
+    pub(crate) fn internal_on_line_items_refunded(&mut self, order_id: OrderId, items: &[LineItemRefund], amount: Balance) {
+        let order = self.get_order(order_id.clone());
+        for refund in items.iter() {
+            self.internal_restore_stock(&order.merchant_id, &refund.sku, refund.quantity);
+        }
+        let remaining_amount = order.amount - order.refunded_amount + amount;
+        self.internal_clawback_reward(order_id, amount, remaining_amount);
+    }
+
+    pub(crate) fn internal_refund_line_items(&mut self, order_id: OrderId, items: Vec<LineItemRefund>) -> PromiseOrValue<U128> {
+        let mut order = self.get_order(order_id.clone());
+        assert!(order.is_completed && !order.is_refund, "ERROR_ORDER_NOT_REFUNDABLE");
+        assert!(order.issued_gift_card.is_none(), "ERROR_GIFT_CARD_NOT_REFUNDABLE");
+        assert!(order.nft_payment.is_none(), "ERROR_NFT_ORDER_NOT_SPLITTABLE");
+
+        let amount = line_items_refund_amount(&order, &items);
+        for refund in items.iter() {
+            let line = order.line_items.iter_mut().find(|line| line.sku == refund.sku).unwrap();
+            line.refunded_quantity += refund.quantity;
+        }
+        order.refunded_amount += amount;
+        // the gift card share goes back to the card as store credit
+        self.internal_refund_gift_card(&mut order);
+        self.orders.insert(&order_id, &order);
+        self.internal_debit_merchant(&order, amount);
+
+        if amount == 0 {
+            self.internal_on_line_items_refunded(order_id, &items, 0);
+            return PromiseOrValue::Value(U128(0));
+        }
+
+        let promise = self.internal_transfer(order.token_id, order.payer_id, amount, "Refund line items from payment contract")
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_attached_deposit(0)
+                    .with_static_gas(REFUND_CALLBACK_GAS)
+                    .on_refund_line_items(order_id, items, U128(amount))
+            );
+        PromiseOrValue::Promise(promise)
+    }
+}
+
+/*
+ * Check line items of a new order add up to the order total, orders without line items are allowed
+ * - At most MAX_LINE_ITEMS lines, SKUs of at most MAX_SKU_LENGTH bytes
+ */
+pub(crate) fn assert_line_items(line_items: &mut [LineItem], expected_total: Balance) {
+    if line_items.is_empty() {
+        return;
+    }
+    assert!(line_items.len() <= MAX_LINE_ITEMS, "ERROR_TOO_MANY_LINE_ITEMS");
+
+    let mut skus = HashSet::new();
+    let mut total: Balance = 0;
+    for line in line_items.iter_mut() {
+        assert!(!line.sku.is_empty() && line.sku.len() <= MAX_SKU_LENGTH, "ERROR_INVALID_SKU");
+        assert!(line.quantity > 0, "ERROR_INVALID_QUANTITY");
+        assert!(skus.insert(line.sku.clone()), "ERROR_DUPLICATED_SKU");
+        line.refunded_quantity = 0;
+        total += line.total();
+    }
+    assert_eq!(total, expected_total, "ERROR_LINE_ITEMS_TOTAL_MISMATCH");
+}
+
+/*
+ * Amount of the paid token to refund for some line items:
+ * order.amount * refunded line totals / line items total
+ */
+pub(crate) fn line_items_refund_amount(order: &Order, items: &[LineItemRefund]) -> Balance {
+    assert!(!items.is_empty(), "ERROR_NO_LINE_ITEMS");
+    let items_total: Balance = order.line_items.iter().map(|line| line.total()).sum();
+    assert!(items_total > 0, "ERROR_NO_LINE_ITEMS");
+
+    let mut skus = HashSet::new();
+    let mut refund_total = U256::zero();
+    for refund in items.iter() {
+        assert!(skus.insert(refund.sku.clone()), "ERROR_DUPLICATED_SKU");
+        let line = order.line_items.iter().find(|line| line.sku == refund.sku).expect("NOT_FOUND_SKU");
+        assert!(
+            refund.quantity > 0 && line.refunded_quantity + refund.quantity <= line.quantity,
+            "ERROR_INVALID_QUANTITY"
+        );
+        refund_total += U256::from(line.total()) * U256::from(refund.quantity) / U256::from(line.quantity);
+    }
+
+    (refund_total * U256::from(order.amount) / U256::from(items_total)).as_u128()
+}
+
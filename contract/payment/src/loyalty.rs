@@ -0,0 +1,148 @@
+use near_sdk::{AccountId, Balance, PromiseResult, env, ext_contract, log, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub const REWARD_GAS: Gas = Gas(10_000_000_000_000);
+// Gas of a refund callback: its own work plus the reward burn it may schedule
+pub const REFUND_CALLBACK_GAS: Gas = Gas(TRANSFER_GAS.0 + REWARD_GAS.0);
+// Storage of the reward record in the reward token, paid by this contract, the token refunds the rest
+pub const REWARD_STORAGE_DEPOSIT: Balance = 5_000_000_000_000_000_000_000;
+
+const MAX_REWARD_BASIS_POINTS: u16 = 10_000;
+
+/**
+ * Loyalty rewards minted to the payer of each paid order priced by the merchant
+ * - reward = paid amount * reward_basis_points / 10000, rescaled from the paid token decimals to the reward token decimals
+ * - The payment contract must be a minter of the reward token (see add_minter of the VBI token)
+ * - Payers must be registered with the reward token (storage_deposit) to earn rewards
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LoyaltyConfig {
+    pub reward_token_id: AccountId,
+    pub reward_basis_points: u16
+}
+
+#[ext_contract(ext_reward_token)]
+pub trait RewardToken {
+    fn ft_mint(&mut self, account_id: AccountId, amount: U128, reward_id: String, memo: Option<String>);
+    fn ft_burn(&mut self, account_id: AccountId, amount: U128, reward_id: String, memo: Option<String>) -> U128;
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Enable, change or disable (None) loyalty rewards, rewards of past orders are kept
+     */
+    pub fn set_loyalty_config(&mut self, config: Option<LoyaltyConfig>) {
+        self.assert_owner();
+        if let Some(config) = &config {
+            assert!(
+                config.reward_basis_points > 0 && config.reward_basis_points <= MAX_REWARD_BASIS_POINTS,
+                "ERROR_INVALID_REWARD_RATE"
+            );
+            // decimals of the reward token come from the accepted tokens metadata
+            self.payment_method_of(&Some(config.reward_token_id.clone()));
+        }
+        self.loyalty_config = config;
+    }
+
+    pub fn get_loyalty_config(&self) -> Option<LoyaltyConfig> {
+        self.loyalty_config.clone()
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Mint the reward of a new order to its payer, the reward is recorded on the order
+     * - Orders priced by their payer earn no reward, each mint spends REWARD_STORAGE_DEPOSIT of this contract
+     */
+    pub(crate) fn internal_mint_reward(&mut self, order: &mut Order) {
+        let config = match &self.loyalty_config {
+            Some(config) if order.price_source != PriceSource::Payer => config.clone(),
+            _ => return
+        };
+
+        let reward = self.internal_reward_amount(&config, order);
+        if reward == 0 {
+            return;
+        }
+        order.reward_token_id = Some(config.reward_token_id.clone());
+        order.reward_amount = reward;
+
+        // the reward is identified by its order, so only the reward of the order can be burned back
+        ext_reward_token::ext(config.reward_token_id)
+            .with_attached_deposit(REWARD_STORAGE_DEPOSIT)
+            .with_static_gas(REWARD_GAS)
+            .ft_mint(
+                order.payer_id.clone(),
+                U128(reward),
+                order.order_id.clone(),
+                Some(format!("Loyalty reward of order {}", order.order_id))
+            )
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_static_gas(REWARD_GAS)
+                    .on_reward_minted(order.order_id.clone())
+            );
+    }
+
+    pub(crate) fn internal_on_reward_minted(&mut self, order_id: OrderId) {
+        assert_eq!(env::promise_results_count(), 1, "ERROR_TOO_MANY_RESULTS");
+        if let PromiseResult::Failed = env::promise_result(0) {
+            // nothing to claw back later
+            let mut order = self.get_order(order_id.clone());
+            order.reward_amount = 0;
+            self.orders.insert(&order_id, &order);
+            log!("Failed to mint the loyalty reward of order {}", order_id);
+        }
+    }
+
+    /*
+     * Burn the share of the order reward matching a refund
+     * - remaining_amount: amount of the order not refunded before this refund
+     */
+    pub(crate) fn internal_clawback_reward(&mut self, order_id: OrderId, refund_amount: Balance, remaining_amount: Balance) {
+        let mut order = self.get_order(order_id.clone());
+        let reward_token_id = match &order.reward_token_id {
+            Some(reward_token_id) if order.reward_amount > 0 && remaining_amount > 0 => reward_token_id.clone(),
+            _ => return
+        };
+
+        let clawback = (U256::from(order.reward_amount) * U256::from(refund_amount) / U256::from(remaining_amount)).as_u128();
+        if clawback == 0 {
+            return;
+        }
+        order.reward_amount -= clawback;
+        self.orders.insert(&order_id, &order);
+
+        // the payer may have spent the reward already, the token burns what is left
+        ext_reward_token::ext(reward_token_id)
+            .with_static_gas(REWARD_GAS)
+            .ft_burn(order.payer_id, U128(clawback), order_id.clone(), Some(format!("Loyalty reward refund of order {}", order_id)));
+    }
+
+    /*
+     * Orders paid with a token of unknown decimals earn no reward
+     */
+    fn internal_reward_amount(&self, config: &LoyaltyConfig, order: &Order) -> Balance {
+        let paid_decimals = match &order.token_id {
+            None => Some(NEAR_DECIMALS),
+            Some(token_id) => self.accepted_tokens.get(token_id).and_then(|token| token.decimals)
+        };
+        let reward_decimals = self.accepted_tokens.get(&config.reward_token_id).and_then(|token| token.decimals);
+        let (paid_decimals, reward_decimals) = match (paid_decimals, reward_decimals) {
+            (Some(paid_decimals), Some(reward_decimals)) => (paid_decimals, reward_decimals),
+            _ => return 0
+        };
+
+        let reward = U256::from(order.amount) * U256::from(config.reward_basis_points) * U256::exp10(reward_decimals as usize)
+            / (U256::from(MAX_REWARD_BASIS_POINTS) * U256::exp10(paid_decimals as usize));
+        if reward > U256::from(u128::MAX) {
+            return 0;
+        }
+        reward.as_u128()
+    }
+}
@@ -0,0 +1,89 @@
+use near_sdk::{AccountId, CurveType, Duration, PublicKey, Timestamp, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/**
+ * Merchant selling through the payment contract
+ * - public_key: ed25519 key of the merchant backend used to sign checkout orders
+ * - tip_recipient: account receiving the tips of the merchant's orders, the merchant itself if None
+ * - payment_hook: merchant contract called when an order is paid, see set_payment_hook
+ * - hold_period: chargeback window before payments become withdrawable, see get_merchant_balance
+ * - jurisdiction: tax jurisdiction code of the merchant orders, see set_tax_rate
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Merchant {
+    pub merchant_id: AccountId,
+    pub public_key: Option<PublicKey>,
+    pub created_at: Timestamp,
+    pub tip_recipient: Option<AccountId>,
+    pub payment_hook: Option<PaymentHook>,
+    pub hold_period: Duration,
+    pub jurisdiction: Option<String>,
+    // Commission paid to referrers, in basis points of the order amount before tax
+    pub commission_basis_points: u16
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    pub fn register_merchant(&mut self, merchant_id: AccountId, public_key: Option<PublicKey>) {
+        self.assert_owner();
+        assert!(self.merchants.get(&merchant_id).is_none(), "ERROR_MERCHANT_ALREADY_EXISTS");
+        self.internal_register_merchant(merchant_id, public_key);
+    }
+
+    /*
+     * Rotate or remove the signing key of the calling merchant
+     */
+    pub fn set_merchant_key(&mut self, public_key: Option<PublicKey>) {
+        let mut merchant = self.get_merchant(self.assert_merchant());
+        if let Some(public_key) = &public_key {
+            assert_ed25519(public_key);
+        }
+        merchant.public_key = public_key;
+        self.merchants.insert(&merchant.merchant_id, &merchant);
+    }
+
+    pub fn set_tip_recipient(&mut self, tip_recipient: Option<AccountId>) {
+        let mut merchant = self.get_merchant(self.assert_merchant());
+        merchant.tip_recipient = tip_recipient;
+        self.merchants.insert(&merchant.merchant_id, &merchant);
+    }
+
+    pub fn get_merchant(&self, merchant_id: AccountId) -> Merchant {
+        self.merchants.get(&merchant_id).expect("NOT_FOUND_MERCHANT")
+    }
+}
+
+impl EcommerceContract {
+    pub(crate) fn internal_register_merchant(&mut self, merchant_id: AccountId, public_key: Option<PublicKey>) {
+        if let Some(public_key) = &public_key {
+            assert_ed25519(public_key);
+        }
+        self.merchants.insert(&merchant_id, &Merchant {
+            merchant_id: merchant_id.clone(),
+            public_key,
+            created_at: env::block_timestamp(),
+            tip_recipient: None,
+            payment_hook: None,
+            hold_period: DEFAULT_HOLD_PERIOD,
+            jurisdiction: None,
+            commission_basis_points: 0
+        });
+    }
+
+    /*
+     * Check the caller is a registered merchant and return its account id
+     */
+    pub(crate) fn assert_merchant(&self) -> AccountId {
+        let merchant_id = env::predecessor_account_id();
+        assert!(self.merchants.get(&merchant_id).is_some(), "ERROR_NOT_MERCHANT");
+        merchant_id
+    }
+}
+
+fn assert_ed25519(public_key: &PublicKey) {
+    assert!(matches!(public_key.curve_type(), CurveType::ED25519), "ERROR_KEY_NOT_ED25519");
+}
@@ -0,0 +1,335 @@
+use near_sdk::{AccountId, Balance, Duration, PromiseResult, Timestamp, env, log, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+// 7 days
+pub const DEFAULT_HOLD_PERIOD: Duration = 7 * 24 * 60 * 60 * 1_000_000_000;
+// Queued releases looked at per call, the following ones mature on the next calls
+const MAX_RELEASES_PER_CALL: u64 = 50;
+
+pub type BalanceKey = (AccountId, Option<AccountId>);
+
+/**
+ * Payment of an order held until release_at, refunds of the order draw from it first
+ * - queue_index: position in the release queue of the balance, see MerchantBalance
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingRelease {
+    pub order_id: OrderId,
+    pub amount: U128,
+    pub release_at: Timestamp,
+    pub queue_index: u64
+}
+
+/**
+ * Funds of a merchant or a referrer in one token
+ * - pending: paid orders still in the chargeback window, held per order in pending_releases
+ * - available: withdrawable with withdraw, matured releases move here lazily in payment order
+ * - release_head / release_tail: queue of the orders held, see get_pending_releases
+ * - withdrawn_today: amount withdrawn or requested for withdrawal on withdrawn_day, checked against the multisig threshold
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MerchantBalance {
+    pub available: U128,
+    pub pending: U128,
+    pub release_head: u64,
+    pub release_tail: u64,
+    pub withdrawn_day: u64,
+    pub withdrawn_today: U128
+}
+
+impl Default for MerchantBalance {
+    fn default() -> Self {
+        Self {
+            available: U128(0),
+            pending: U128(0),
+            release_head: 0,
+            release_tail: 0,
+            withdrawn_day: 0,
+            withdrawn_today: U128(0)
+        }
+    }
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Change the chargeback window of a merchant, releases already scheduled are kept
+     */
+    pub fn set_hold_period(&mut self, merchant_id: AccountId, hold_period: Duration) {
+        self.assert_owner();
+        let mut merchant = self.get_merchant(merchant_id);
+        merchant.hold_period = hold_period;
+        self.merchants.insert(&merchant.merchant_id, &merchant);
+    }
+
+    /*
+     * Balance of a merchant at the current block
+     */
+    pub fn get_merchant_balance(&self, merchant_id: AccountId, token: Option<AccountId>) -> MerchantBalance {
+        self.due_releases(&(merchant_id, token)).0
+    }
+
+    pub fn get_pending_release(&self, merchant_id: AccountId, token: Option<AccountId>, order_id: OrderId) -> Option<PendingRelease> {
+        self.pending_releases.get(&(merchant_id, token, order_id))
+    }
+
+    /*
+     * Releases stored for a merchant in payment order, from_index is a position in the release queue
+     * - Matured releases stay listed until a later call moves them to the available balance
+     */
+    pub fn get_pending_releases(&self, merchant_id: AccountId, token: Option<AccountId>, from_index: u64, limit: u64) -> Vec<PendingRelease> {
+        let balance = self.merchant_balances.get(&(merchant_id.clone(), token.clone())).unwrap_or_default();
+        (from_index.max(balance.release_head)..balance.release_tail.min(from_index.saturating_add(limit)))
+            .filter_map(|index| self.queued_release(&(merchant_id.clone(), token.clone()), index))
+            .collect()
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Release at a position of the queue, None once it was fully refunded or released early
+     */
+    fn queued_release(&self, key: &BalanceKey, index: u64) -> Option<PendingRelease> {
+        let order_id = self.release_queue.get(&(key.0.clone(), key.1.clone(), index))?;
+        self.pending_releases.get(&(key.0.clone(), key.1.clone(), order_id))
+            .filter(|release| release.queue_index == index)
+    }
+
+    /*
+     * Balance with the releases due at the current block moved to available, with the orders released
+     * - The queue stops at the first release not due, at most MAX_RELEASES_PER_CALL releases are looked at
+     */
+    fn due_releases(&self, key: &BalanceKey) -> (MerchantBalance, Vec<OrderId>) {
+        let mut balance = self.merchant_balances.get(key).unwrap_or_default();
+        let now = env::block_timestamp();
+        let end = balance.release_tail.min(balance.release_head + MAX_RELEASES_PER_CALL);
+        let mut released = vec![];
+        while balance.release_head < end {
+            if let Some(release) = self.queued_release(key, balance.release_head) {
+                if release.release_at > now {
+                    break;
+                }
+                balance.available = U128(balance.available.0 + release.amount.0);
+                balance.pending = U128(balance.pending.0 - release.amount.0);
+                released.push(release.order_id);
+            }
+            balance.release_head += 1;
+        }
+        (balance, released)
+    }
+
+    /*
+     * Move the releases due to the available balance and drop them from storage, the caller saves the balance
+     */
+    fn internal_release_due(&mut self, key: &BalanceKey) -> MerchantBalance {
+        let head = self.merchant_balances.get(key).map_or(0, |balance| balance.release_head);
+        let (balance, released) = self.due_releases(key);
+        for index in head..balance.release_head {
+            self.release_queue.remove(&(key.0.clone(), key.1.clone(), index));
+        }
+        for order_id in released {
+            self.pending_releases.remove(&(key.0.clone(), key.1.clone(), order_id));
+        }
+        balance
+    }
+
+    /*
+     * Campaign funds settle to the merchant at the deadline and NFT payments hold no funds
+     */
+    fn is_merchant_credited(order: &Order) -> bool {
+        order.campaign_id.is_none() && order.nft_payment.is_none()
+    }
+
+    /*
+     * Hold a payment of the order for the merchant hold period
+     * - Its tax share goes to the tax balance, its commission share is held the same way for the referrer
+     */
+    pub(crate) fn internal_credit_merchant(&mut self, order: &Order, amount: Balance) {
+        if amount == 0 || !Self::is_merchant_credited(order) {
+            return;
+        }
+        let tax = Self::order_tax_share(order, amount);
+        self.internal_credit_tax(order, tax);
+        let commission = Self::order_commission_share(order, amount);
+
+        let hold_period = self.get_merchant(order.merchant_id.clone()).hold_period;
+        if let Some(referrer_id) = &order.referrer_id {
+            self.internal_hold_funds(referrer_id, order, commission, hold_period);
+        }
+        self.internal_hold_funds(&order.merchant_id, order, amount - tax - commission, hold_period);
+    }
+
+    /*
+     * Whether the merchant can cover a refund of the order
+     */
+    pub(crate) fn can_debit_merchant(&self, order: &Order, amount: Balance) -> bool {
+        if !Self::is_merchant_credited(order) {
+            return true;
+        }
+        let tax = Self::order_tax_share(order, amount);
+        let commission = Self::order_commission_share(order, amount);
+        let referrer_amount = order.referrer_id.as_ref()
+            .map_or(0, |referrer_id| self.funds_for_order(referrer_id, order).min(commission));
+        self.funds_for_order(&order.merchant_id, order) >= amount - tax - referrer_amount && self.can_debit_tax(order, tax)
+    }
+
+    /*
+     * Take a refund of the order from its pending releases first, then from the available balance
+     * - The tax share of the refund is taken from the tax balance
+     * - The commission share is taken from the referrer, the merchant covers what the referrer already withdrew
+     */
+    pub(crate) fn internal_debit_merchant(&mut self, order: &Order, amount: Balance) {
+        if amount == 0 || !Self::is_merchant_credited(order) {
+            return;
+        }
+        let tax = Self::order_tax_share(order, amount);
+        self.internal_debit_tax(order, tax);
+        let commission = Self::order_commission_share(order, amount);
+        let referrer_amount = match &order.referrer_id {
+            Some(referrer_id) => self.internal_take_funds(referrer_id, order, commission),
+            None => 0
+        };
+
+        let merchant_amount = amount - tax - referrer_amount;
+        let taken = self.internal_take_funds(&order.merchant_id, order, merchant_amount);
+        assert_eq!(taken, merchant_amount, "ERROR_MERCHANT_BALANCE_NOT_ENOUGH");
+    }
+
+    /*
+     * End the chargeback window of an order early, e.g. once the payer confirmed delivery
+     */
+    pub(crate) fn internal_release_order_funds(&mut self, order: &Order) {
+        for account_id in std::iter::once(&order.merchant_id).chain(order.referrer_id.iter()) {
+            let key = (account_id.clone(), order.token_id.clone());
+            let mut balance = self.internal_release_due(&key);
+            if let Some(release) = self.pending_releases.remove(&(key.0.clone(), key.1.clone(), order.order_id.clone())) {
+                balance.available = U128(balance.available.0 + release.amount.0);
+                balance.pending = U128(balance.pending.0 - release.amount.0);
+            }
+            self.merchant_balances.insert(&key, &balance);
+        }
+    }
+
+    /*
+     * Hold amount for the order, added to the release of the order when it is still held
+     */
+    fn internal_hold_funds(&mut self, account_id: &AccountId, order: &Order, amount: Balance, hold_period: Duration) {
+        if amount == 0 {
+            return;
+        }
+        let key = (account_id.clone(), order.token_id.clone());
+        let mut balance = self.internal_release_due(&key);
+        let release_key = (key.0.clone(), key.1.clone(), order.order_id.clone());
+        let release_at = env::block_timestamp() + hold_period;
+        let release = match self.pending_releases.get(&release_key) {
+            Some(release) => PendingRelease {
+                amount: U128(release.amount.0 + amount),
+                release_at: release.release_at.max(release_at),
+                ..release
+            },
+            None => {
+                self.release_queue.insert(&(key.0.clone(), key.1.clone(), balance.release_tail), &order.order_id);
+                balance.release_tail += 1;
+                PendingRelease { order_id: order.order_id.clone(), amount: U128(amount), release_at, queue_index: balance.release_tail - 1 }
+            }
+        };
+        self.pending_releases.insert(&release_key, &release);
+        balance.pending = U128(balance.pending.0 + amount);
+        self.merchant_balances.insert(&key, &balance);
+    }
+
+    /*
+     * Funds of an account a refund of the order can draw from: held for the order plus available
+     */
+    fn funds_for_order(&self, account_id: &AccountId, order: &Order) -> Balance {
+        let key = (account_id.clone(), order.token_id.clone());
+        let (balance, released) = self.due_releases(&key);
+        let held = match self.pending_releases.get(&(key.0, key.1, order.order_id.clone())) {
+            Some(release) if !released.contains(&order.order_id) => release.amount.0,
+            _ => 0
+        };
+        held + balance.available.0
+    }
+
+    /*
+     * Take up to amount from the release of the order, then from the available balance
+     * - Return the amount taken
+     */
+    fn internal_take_funds(&mut self, account_id: &AccountId, order: &Order, amount: Balance) -> Balance {
+        if amount == 0 {
+            return 0;
+        }
+        let key = (account_id.clone(), order.token_id.clone());
+        let mut balance = self.internal_release_due(&key);
+
+        let release_key = (key.0.clone(), key.1.clone(), order.order_id.clone());
+        let mut remaining = amount;
+        if let Some(mut release) = self.pending_releases.get(&release_key) {
+            let taken = remaining.min(release.amount.0);
+            release.amount = U128(release.amount.0 - taken);
+            remaining -= taken;
+            if release.amount.0 > 0 {
+                self.pending_releases.insert(&release_key, &release);
+            } else {
+                self.pending_releases.remove(&release_key);
+            }
+        }
+        balance.pending = U128(balance.pending.0 - (amount - remaining));
+
+        let taken = remaining.min(balance.available.0);
+        balance.available = U128(balance.available.0 - taken);
+        self.merchant_balances.insert(&key, &balance);
+        amount - remaining + taken
+    }
+
+    pub(crate) fn internal_credit_available(&mut self, account_id: &AccountId, token: &Option<AccountId>, amount: Balance) {
+        let key = (account_id.clone(), token.clone());
+        let mut balance = self.merchant_balances.get(&key).unwrap_or_default();
+        balance.available = U128(balance.available.0 + amount);
+        self.merchant_balances.insert(&key, &balance);
+    }
+
+    pub(crate) fn internal_debit_available(&mut self, account_id: &AccountId, token: &Option<AccountId>, amount: Balance) {
+        let key = (account_id.clone(), token.clone());
+        let mut balance = self.internal_release_due(&key);
+        assert!(balance.available.0 >= amount, "ERROR_MERCHANT_BALANCE_NOT_ENOUGH");
+        balance.available = U128(balance.available.0 - amount);
+        self.merchant_balances.insert(&key, &balance);
+    }
+
+    /*
+     * Debit a withdrawal from the available balance and add it to the amount withdrawn today
+     * - Return the amount withdrawn today, so withdrawals split over several calls still reach the multisig threshold
+     */
+    pub(crate) fn internal_debit_withdrawal(&mut self, account_id: &AccountId, token: &Option<AccountId>, amount: Balance) -> Balance {
+        self.internal_debit_available(account_id, token, amount);
+        let key = (account_id.clone(), token.clone());
+        let mut balance = self.merchant_balances.get(&key).unwrap();
+        let day = env::block_timestamp() / DAY;
+        if balance.withdrawn_day != day {
+            balance.withdrawn_day = day;
+            balance.withdrawn_today = U128(0);
+        }
+        balance.withdrawn_today = U128(balance.withdrawn_today.0 + amount);
+        self.merchant_balances.insert(&key, &balance);
+        balance.withdrawn_today.0
+    }
+
+    pub(crate) fn internal_on_merchant_withdraw(&mut self, merchant_id: AccountId, token: Option<AccountId>, amount: U128) -> U128 {
+        assert_eq!(env::promise_results_count(), 1, "ERROR_TOO_MANY_RESULTS");
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_value) => U128(0),
+            PromiseResult::Failed => {
+                self.internal_credit_available(&merchant_id, &token, amount.0);
+                log!("Failed to withdraw {} to {}", amount.0, merchant_id);
+                amount
+            }
+        }
+    }
+}
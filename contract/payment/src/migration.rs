@@ -0,0 +1,107 @@
+use near_sdk::{AccountId, Balance, Timestamp, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+
+use crate::*;
+
+/**
+ * State of the first release, before merchants, balances and the other features were added
+ */
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyEcommerceContract {
+    pub owner_id: AccountId,
+    pub ft_contract_id: AccountId,
+    pub orders: LookupMap<OrderId, LegacyOrder>
+}
+
+/**
+ * Order of the first release, stored under the same prefix as Order
+ */
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyOrder {
+    pub order_id: OrderId,
+    pub payer_id: AccountId,
+    pub payment_method: PaymentMethod,
+    pub amount: Balance,
+    pub received_amount: Balance,
+    pub is_completed: bool,
+    pub is_refund: bool,
+    pub created_at: Timestamp
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Upgrade the state of the first release, call it from the contract account right after deploying the new code
+     * - Every new setting starts from its default, like a contract created with new
+     * - Orders are converted lazily with migrate_orders
+     */
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let old: LegacyEcommerceContract = env::state_read().expect("ERROR_NOT_INITIALIZED");
+        Self::new(old.owner_id, old.ft_contract_id)
+    }
+
+    /*
+     * Convert orders of the first release to the current format, orders already converted are skipped
+     * - Their payments, never withdrawn before, become available to the owner as their merchant
+     */
+    pub fn migrate_orders(&mut self, order_ids: Vec<OrderId>) -> u32 {
+        self.assert_owner();
+        let mut num_migrated = 0;
+        for order_id in order_ids {
+            let mut key = StorageKey::OrderKey.try_to_vec().unwrap();
+            key.extend(order_id.try_to_vec().unwrap());
+            let old = match env::storage_read(&key).and_then(|value| LegacyOrder::try_from_slice(&value).ok()) {
+                Some(old) => old,
+                None => continue
+            };
+
+            let token_id = match old.payment_method {
+                PaymentMethod::FungibleToken => Some(self.ft_contract_id.clone()),
+                _ => None
+            };
+            let order = Order {
+                order_id: old.order_id,
+                merchant_id: self.owner_id.clone(),
+                payer_id: old.payer_id,
+                payment_method: old.payment_method,
+                token_id,
+                amount: old.amount,
+                received_amount: old.received_amount,
+                is_completed: old.is_completed,
+                is_refund: old.is_refund,
+                created_at: old.created_at,
+                fiat_amount: None,
+                rate: None,
+                line_items: vec![],
+                refunded_amount: 0,
+                coupon_code: None,
+                discount: 0,
+                reward_token_id: None,
+                reward_amount: 0,
+                gift_card_payment: None,
+                issued_gift_card: None,
+                campaign_id: None,
+                tip: 0,
+                nft_payment: None,
+                tax: 0,
+                tax_jurisdiction: None,
+                fulfilment: vec![],
+                delivery_confirmed_at: None,
+                referrer_id: None,
+                commission: 0,
+                price_source: PriceSource::Payer
+            };
+            if order.is_completed && !order.is_refund {
+                self.internal_credit_available(&order.merchant_id, &order.token_id, order.amount);
+            }
+            // Inserting over the legacy value would decode it as an Order
+            env::storage_remove(&key);
+            self.orders.insert(&order_id, &order);
+            num_migrated += 1;
+        }
+        num_migrated
+    }
+}
@@ -0,0 +1,195 @@
+use near_sdk::{AccountId, Balance, Duration, PromiseOrValue, Timestamp, env, log, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub type RequestId = u64;
+
+/**
+ * Threshold approval settings
+ * - amount_threshold: refunds/withdrawals strictly above this amount in normalized units (NORMALIZED_DECIMALS)
+ *   need approvals, in every token, including returns
+ *   received by merchants and refunds of failed payment hooks, withdrawals count everything the account
+ *   withdrew or requested in the same token during the day
+ * - num_confirmations: number of approvers (M of N) required to execute a request
+ * - request_lifetime: nanoseconds before a pending request expires
+ * - Refunds of failed campaigns (refund_campaign) are exempt: anyone can trigger them once the campaign failed,
+ *   and they only send each order amount back to its own payer
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MultisigConfig {
+    pub approvers: Vec<AccountId>,
+    pub num_confirmations: u32,
+    pub amount_threshold: U128,
+    pub request_lifetime: Duration
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum MultisigAction {
+    Refund { order_id: OrderId },
+    RefundLineItems { order_id: OrderId, items: Vec<LineItemRefund> },
+    ReceiveReturn { order_id: OrderId },
+    Withdraw { token: Option<AccountId>, receiver_id: AccountId, amount: U128 },
+    WithdrawTax { jurisdiction: String, token: Option<AccountId>, receiver_id: AccountId, amount: U128 },
+    SetConfig { config: Option<MultisigConfig> }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MultisigRequest {
+    pub request_id: RequestId,
+    pub action: MultisigAction,
+    pub proposer_id: AccountId,
+    pub confirmations: Vec<AccountId>,
+    pub created_at: Timestamp,
+    pub expires_at: Timestamp
+}
+
+impl MultisigConfig {
+    fn assert_valid(&self) {
+        assert!(self.num_confirmations > 0, "ERROR_INVALID_NUM_CONFIRMATIONS");
+        assert!(self.num_confirmations as usize <= self.approvers.len(), "ERROR_NOT_ENOUGH_APPROVERS");
+        assert!(self.request_lifetime > 0, "ERROR_INVALID_REQUEST_LIFETIME");
+    }
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Enable, update or disable threshold approvals
+     * - Once enabled, changing the config is itself a request that needs approvals
+     */
+    pub fn set_multisig_config(&mut self, config: Option<MultisigConfig>) -> Option<RequestId> {
+        self.assert_owner();
+        if let Some(config) = &config {
+            config.assert_valid();
+        }
+
+        if self.multisig_config.is_some() {
+            return Some(self.internal_add_request(MultisigAction::SetConfig { config }));
+        }
+
+        self.multisig_config = config;
+        None
+    }
+
+    /*
+     * Approve a pending request
+     * - Only approvers can confirm, once per request, before it expires
+     * - The request is executed as soon as enough confirmations are collected
+     */
+    pub fn confirm_request(&mut self, request_id: RequestId) -> PromiseOrValue<U128> {
+        let config = self.multisig_config.clone().expect("ERROR_MULTISIG_DISABLED");
+        let approver_id = env::predecessor_account_id();
+        assert!(config.approvers.contains(&approver_id), "ERROR_NOT_APPROVER");
+
+        let mut request = self.get_request(request_id);
+        assert!(env::block_timestamp() < request.expires_at, "ERROR_REQUEST_EXPIRED");
+        assert!(!request.confirmations.contains(&approver_id), "ERROR_ALREADY_CONFIRMED");
+        request.confirmations.push(approver_id);
+
+        // Only approvers still in the current config count toward the threshold
+        let confirmed = request.confirmations.iter()
+            .filter(|account_id| config.approvers.contains(account_id))
+            .count();
+
+        if confirmed < config.num_confirmations as usize {
+            self.multisig_requests.insert(&request_id, &request);
+            return PromiseOrValue::Value(U128(0));
+        }
+
+        self.multisig_requests.remove(&request_id);
+        log!("Executing multisig request {}", request_id);
+
+        match request.action {
+            MultisigAction::Refund { order_id } => self.internal_refund(order_id),
+            MultisigAction::RefundLineItems { order_id, items } => self.internal_refund_line_items(order_id, items),
+            MultisigAction::ReceiveReturn { order_id } => self.internal_receive_return(order_id),
+            MultisigAction::Withdraw { token, receiver_id, amount } => {
+                self.internal_withdraw(token, receiver_id, amount)
+            }
+            MultisigAction::WithdrawTax { jurisdiction, token, receiver_id, amount } => {
+                self.internal_withdraw_tax(jurisdiction, token, receiver_id, amount)
+            }
+            MultisigAction::SetConfig { config } => {
+                self.multisig_config = config;
+                PromiseOrValue::Value(U128(0))
+            }
+        }
+    }
+
+    /*
+     * Cancel a pending request
+     * - The proposer or the owner can cancel at any time, anyone can clean up an expired request
+     * - The amount reserved by a withdrawal goes back to the available balance
+     */
+    pub fn delete_request(&mut self, request_id: RequestId) {
+        let request = self.get_request(request_id);
+        let caller_id = env::predecessor_account_id();
+        assert!(
+            caller_id == request.proposer_id
+                || caller_id == self.owner_id
+                || env::block_timestamp() >= request.expires_at,
+            "ERROR_NOT_ALLOWED"
+        );
+
+        self.multisig_requests.remove(&request_id);
+        if let MultisigAction::Withdraw { token, receiver_id, amount } = request.action {
+            self.internal_credit_available(&receiver_id, &token, amount.0);
+        }
+        log!("Deleted multisig request {}", request_id);
+    }
+
+    pub fn get_request(&self, request_id: RequestId) -> MultisigRequest {
+        self.multisig_requests.get(&request_id).expect("NOT_FOUND_REQUEST_ID")
+    }
+
+    pub fn get_multisig_config(&self) -> Option<MultisigConfig> {
+        self.multisig_config.clone()
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Compare an amount of the token with the normalized threshold, amounts of a token with unknown decimals always need approvals
+     */
+    pub(crate) fn requires_multisig(&self, token: &Option<AccountId>, amount: Balance) -> bool {
+        let config = match &self.multisig_config {
+            Some(config) => config,
+            None => return false
+        };
+        let decimals = match token {
+            None => Some(NEAR_DECIMALS),
+            Some(token_id) => self.accepted_tokens.get(token_id).and_then(|token| token.decimals)
+        };
+        match decimals {
+            Some(decimals) => U256::from(amount) * U256::exp10(NORMALIZED_DECIMALS as usize)
+                > U256::from(config.amount_threshold.0) * U256::exp10(decimals as usize),
+            None => true
+        }
+    }
+
+    pub(crate) fn internal_add_request(&mut self, action: MultisigAction) -> RequestId {
+        let config = self.multisig_config.as_ref().expect("ERROR_MULTISIG_DISABLED");
+        let request_id = self.next_request_id;
+        let created_at = env::block_timestamp();
+
+        let request = MultisigRequest {
+            request_id,
+            action,
+            proposer_id: env::predecessor_account_id(),
+            confirmations: vec![],
+            created_at,
+            expires_at: created_at + config.request_lifetime
+        };
+
+        self.multisig_requests.insert(&request_id, &request);
+        self.next_request_id += 1;
+        log!("Created multisig request {}", request_id);
+
+        request_id
+    }
+}
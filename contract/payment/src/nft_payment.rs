@@ -0,0 +1,163 @@
+use near_sdk::{AccountId, Gas, Promise, PromiseOrValue, PromiseResult, env, ext_contract, log, near_bindgen, serde_json};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub const NFT_TRANSFER_GAS: Gas = Gas(15_000_000_000_000);
+pub const NFT_SETTLED_CALLBACK_GAS: Gas = Gas(10_000_000_000_000);
+
+/**
+ * Whitelisted NFT collection, each NFT is worth value (in the smallest unit of token, None for NEAR)
+ * when paying or trading in an order
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftCollection {
+    pub nft_contract_id: AccountId,
+    pub token: Option<AccountId>,
+    pub value: U128
+}
+
+/**
+ * NFT held in escrow by the payment contract for an order, returned on refund
+ * - is_settled: the NFT was transferred to the merchant, the order can no longer be refunded
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftPayment {
+    pub nft_contract_id: AccountId,
+    pub token_id: String,
+    pub is_settled: bool
+}
+
+#[ext_contract(ext_nft)]
+pub trait NonFungibleTokenCore {
+    fn nft_transfer(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>);
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    pub fn add_accepted_nft(&mut self, nft_contract_id: AccountId, token: Option<AccountId>, value: U128) {
+        self.assert_owner();
+        assert!(value.0 > 0, "ERROR_INVALID_AMOUNT");
+        self.payment_method_of(&token);
+        self.accepted_nfts.insert(&nft_contract_id, &NftCollection { nft_contract_id: nft_contract_id.clone(), token, value });
+    }
+
+    pub fn remove_accepted_nft(&mut self, nft_contract_id: AccountId) {
+        self.assert_owner();
+        self.accepted_nfts.remove(&nft_contract_id).expect("ERROR_NFT_NOT_ACCEPTED");
+    }
+
+    pub fn get_accepted_nfts(&self) -> Vec<NftCollection> {
+        self.accepted_nfts.values().collect()
+    }
+
+    /*
+     * Release the escrowed NFT of an order to its merchant, anyone can call it
+     * - Once the payer confirmed delivery (done automatically by confirm_delivery) or the merchant hold period is over
+     * - A failed transfer can be settled again
+     */
+    pub fn settle_nft_payment(&mut self, order_id: OrderId) -> Promise {
+        let order = self.get_order(order_id);
+        assert!(order.is_completed && !order.is_refund, "ERROR_ORDER_NOT_SETTLEABLE");
+        let hold_period = self.get_merchant(order.merchant_id.clone()).hold_period;
+        assert!(
+            order.delivery_confirmed_at.is_some() || env::block_timestamp() >= order.created_at + hold_period,
+            "ERROR_NFT_PAYMENT_HELD"
+        );
+        self.internal_settle_nft_payment(order)
+    }
+}
+
+/*
+ * Pay an order with a whitelisted NFT, msg is the same as ft_on_transfer
+ * - The NFT value must cover the order, no change is given
+ * - Panicking returns the NFT to its owner
+ */
+#[near_bindgen]
+impl NonFungibleTokenReceiver for EcommerceContract {
+    fn nft_on_transfer(&mut self, sender_id: AccountId, previous_owner_id: AccountId, token_id: String, msg: String) -> PromiseOrValue<bool> {
+        // sender_id may be an approved account, the NFT owner pays and gets it back on refund
+        let _ = sender_id;
+        let nft_contract_id = env::predecessor_account_id();
+        let collection = self.accepted_nfts.get(&nft_contract_id).expect("ERROR_NFT_NOT_ACCEPTED");
+        let FTMessage {order_id, order_amount, quote_id, max_amount, signed_order, line_items, coupon_code, gift_card, campaign_id, tip, referrer} = serde_json::from_str(&msg).expect("ERROR_NOT_VALID_MESSAGE");
+        assert!(
+            gift_card.is_none() && campaign_id.is_none() && tip.is_none() && referrer.is_none()
+                && self.installment_plans.get(&order_id).is_none(),
+            "ERROR_NOT_ALLOWED_FOR_NFT"
+        );
+
+        self.internal_pay_order(Payment {
+            order_id,
+            payer_id: previous_owner_id,
+            payment_method: PaymentMethod::NonFungibleToken,
+            token_id: collection.token,
+            order_amount: order_amount.0,
+            received_amount: collection.value.0,
+            quote_id,
+            max_amount: max_amount.map(|amount| amount.0),
+            signed_order,
+            line_items,
+            coupon_code,
+            gift_card: None,
+            campaign_id: None,
+            tip: 0,
+            nft_payment: Some(NftPayment { nft_contract_id, token_id, is_settled: false }),
+            referrer: None
+        });
+
+        // keep the NFT
+        PromiseOrValue::Value(false)
+    }
+}
+
+impl EcommerceContract {
+    pub(crate) fn internal_transfer_nft(&self, nft_payment: NftPayment, receiver_id: AccountId, memo: &str) -> Promise {
+        ext_nft::ext(nft_payment.nft_contract_id)
+            .with_attached_deposit(1)
+            .with_static_gas(NFT_TRANSFER_GAS)
+            .nft_transfer(receiver_id, nft_payment.token_id, None, Some(memo.to_owned()))
+    }
+
+    pub(crate) fn internal_settle_nft_payment(&mut self, mut order: Order) -> Promise {
+        let mut nft_payment = order.nft_payment.expect("ERROR_NOT_NFT_ORDER");
+        assert!(!nft_payment.is_settled, "ERROR_NFT_ALREADY_SETTLED");
+        nft_payment.is_settled = true;
+        order.nft_payment = Some(nft_payment.clone());
+        self.orders.insert(&order.order_id, &order);
+
+        self.internal_transfer_nft(nft_payment, order.merchant_id, "Settle order from payment contract")
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_attached_deposit(0)
+                    .with_static_gas(NFT_SETTLED_CALLBACK_GAS)
+                    .on_nft_settled(order.order_id)
+            )
+    }
+
+    pub(crate) fn internal_on_nft_settled(&mut self, order_id: OrderId) {
+        assert_eq!(env::promise_results_count(), 1, "ERROR_TOO_MANY_RESULTS");
+        if let PromiseResult::Failed = env::promise_result(0) {
+            // allow settle_nft_payment to retry
+            let mut order = self.get_order(order_id.clone());
+            if let Some(nft_payment) = order.nft_payment.as_mut() {
+                nft_payment.is_settled = false;
+            }
+            self.orders.insert(&order_id, &order);
+            log!("Failed to settle NFT payment of order {}", order_id);
+        }
+    }
+
+    /*
+     * NFTs released to the merchant can no longer be returned to the payer
+     */
+    pub(crate) fn assert_nft_refundable(order: &Order) {
+        assert!(
+            !order.nft_payment.as_ref().is_some_and(|nft_payment| nft_payment.is_settled),
+            "ERROR_NFT_ALREADY_SETTLED"
+        );
+    }
+}
@@ -2,25 +2,96 @@ use near_sdk::Timestamp;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::{AccountId, Balance};
+use near_sdk::json_types::Base58CryptoHash;
 
 use crate::*;
 
-#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
 #[serde(crate= "near_sdk::serde")]
 pub enum PaymentMethod {
     Near,
-    FungibleToken
+    FungibleToken,
+    NonFungibleToken
+}
+
+/**
+ * Who set the order price: the payer for ad-hoc orders, the merchant otherwise
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate= "near_sdk::serde")]
+pub enum PriceSource {
+    Payer,
+    Invoice,
+    SignedOrder,
+    InstallmentPlan
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
 #[serde(crate= "near_sdk::serde")]
 pub struct Order {
     pub order_id: OrderId,
+    pub merchant_id: AccountId,
     pub payer_id: AccountId,
     pub payment_method: PaymentMethod,
+    // FT contract for FungibleToken payments, None for NEAR
+    pub token_id: Option<AccountId>,
     pub amount: Balance,
     pub received_amount: Balance,
     pub is_completed: bool,
     pub is_refund: bool,
-    pub created_at: Timestamp
-}
\ No newline at end of file
+    pub created_at: Timestamp,
+    // Fiat price in cents and the rate used to convert it, only for fiat invoices
+    pub fiat_amount: Option<Balance>,
+    pub rate: Option<Price>,
+    pub line_items: Vec<LineItem>,
+    // Amount already refunded through line item refunds
+    pub refunded_amount: Balance,
+    // Coupon redeemed and the discount it gave, amount is after discount
+    pub coupon_code: Option<String>,
+    pub discount: Balance,
+    // Loyalty reward minted to the payer, reduced by the reward clawed back on refunds
+    pub reward_token_id: Option<AccountId>,
+    pub reward_amount: Balance,
+    // Part paid with a gift card, amount is the part paid on-chain
+    pub gift_card_payment: Option<GiftCardPayment>,
+    // Gift card bought by this order, such orders are not refundable
+    pub issued_gift_card: Option<Base58CryptoHash>,
+    // Crowdfunding campaign of a pre-order
+    pub campaign_id: Option<CampaignId>,
+    // Tip paid on top of amount, sent to the merchant tip recipient and not refunded
+    pub tip: Balance,
+    // NFT paid or traded in for the order, token_id is then the token the NFT value is priced in
+    pub nft_payment: Option<NftPayment>,
+    // Tax included in amount, held in the tax balance of tax_jurisdiction
+    pub tax: Balance,
+    pub tax_jurisdiction: Option<String>,
+    // Fulfilment history set by the merchant, see update_fulfilment
+    pub fulfilment: Vec<FulfilmentUpdate>,
+    pub delivery_confirmed_at: Option<Timestamp>,
+    // Referrer of the payer and its commission, held in the referrer balance like merchant funds
+    pub referrer_id: Option<AccountId>,
+    pub commission: Balance,
+    pub price_source: PriceSource
+}
+
+/**
+ * Payment received through pay_order (NEAR) or ft_on_transfer (FT)
+ */
+pub struct Payment {
+    pub order_id: OrderId,
+    pub payer_id: AccountId,
+    pub payment_method: PaymentMethod,
+    pub token_id: Option<AccountId>,
+    pub order_amount: Balance,
+    pub received_amount: Balance,
+    pub quote_id: Option<String>,
+    pub max_amount: Option<Balance>,
+    pub signed_order: Option<SignedOrder>,
+    pub line_items: Option<Vec<LineItem>>,
+    pub coupon_code: Option<String>,
+    pub gift_card: Option<GiftCardUse>,
+    pub campaign_id: Option<CampaignId>,
+    pub tip: Balance,
+    pub nft_payment: Option<NftPayment>,
+    pub referrer: Option<AccountId>
+}
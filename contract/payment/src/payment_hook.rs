@@ -0,0 +1,95 @@
+use near_sdk::{AccountId, Gas, Promise, PromiseResult, env, log, near_bindgen, serde_json};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub const MAX_PAYMENT_HOOK_GAS: Gas = Gas(50_000_000_000_000);
+// Enough to refund the order when the hook failed: the FT transfer, its refund callback and the hook callback itself
+pub const PAYMENT_HOOK_CALLBACK_GAS: Gas = Gas(TRANSFER_GAS.0 + REFUND_CALLBACK_GAS.0 + 10_000_000_000_000);
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum HookFailure {
+    // Refund the order to its payer
+    Refund,
+    // Keep the payment, the merchant handles the order off-chain
+    Keep
+}
+
+/**
+ * Method of a merchant contract called with {"order": Order} once an order is paid, e.g. to issue a license
+ * - on_failure: what to do with the payment if the hook panics or runs out of gas
+ * - Orders that cannot be refunded (gift card purchases, campaign orders) are always kept
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaymentHook {
+    pub contract_id: AccountId,
+    pub method_name: String,
+    pub gas: Gas,
+    pub on_failure: HookFailure
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct PaymentHookArgs<'a> {
+    order: &'a Order
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Register or remove (None) the payment hook of the calling merchant
+     */
+    pub fn set_payment_hook(&mut self, payment_hook: Option<PaymentHook>) {
+        let mut merchant = self.get_merchant(self.assert_merchant());
+        if let Some(payment_hook) = &payment_hook {
+            assert!(!payment_hook.method_name.is_empty(), "ERROR_INVALID_METHOD_NAME");
+            assert!(payment_hook.gas.0 > 0 && payment_hook.gas <= MAX_PAYMENT_HOOK_GAS, "ERROR_INVALID_GAS");
+        }
+        merchant.payment_hook = payment_hook;
+        self.merchants.insert(&merchant.merchant_id, &merchant);
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Call the payment hook of the order merchant, if any, with the paid order
+     */
+    pub(crate) fn internal_call_payment_hook(&self, order: &Order) {
+        let payment_hook = match self.get_merchant(order.merchant_id.clone()).payment_hook {
+            Some(payment_hook) => payment_hook,
+            None => return
+        };
+
+        let args = serde_json::to_vec(&PaymentHookArgs { order }).unwrap();
+        Promise::new(payment_hook.contract_id)
+            .function_call(payment_hook.method_name, args, 0, payment_hook.gas)
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_attached_deposit(0)
+                    .with_static_gas(PAYMENT_HOOK_CALLBACK_GAS)
+                    .on_payment_hook(order.order_id.clone(), payment_hook.on_failure)
+            );
+    }
+
+    pub(crate) fn internal_on_payment_hook(&mut self, order_id: OrderId, on_failure: HookFailure) {
+        assert_eq!(env::promise_results_count(), 1, "ERROR_TOO_MANY_RESULTS");
+        if let PromiseResult::Successful(_value) = env::promise_result(0) {
+            return;
+        }
+
+        let order = self.get_order(order_id.clone());
+        let is_refundable = order.is_completed && !order.is_refund && order.issued_gift_card.is_none() && order.campaign_id.is_none();
+        if on_failure == HookFailure::Refund && is_refundable && self.requires_multisig(&order.token_id, order.amount) {
+            log!("Payment hook of order {} failed, refund pending approval", order_id);
+            self.internal_add_request(MultisigAction::Refund { order_id });
+        } else if on_failure == HookFailure::Refund && is_refundable {
+            log!("Payment hook of order {} failed, refunding", order_id);
+            self.internal_refund(order_id);
+        } else {
+            log!("Payment hook of order {} failed, payment kept", order_id);
+        }
+    }
+}
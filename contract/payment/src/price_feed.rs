@@ -0,0 +1,184 @@
+use near_sdk::{AccountId, Balance, Duration, Timestamp, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+pub use big_int::U256;
+
+#[allow(clippy::all)]
+mod big_int {
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
+}
+
+pub type AssetId = String;
+
+/// Asset id used for prices of the native NEAR token, FT prices use the token contract id.
+pub const NEAR_ASSET_ID: &str = "near";
+/// Fiat amounts are stored in cents (USD with 2 decimals).
+pub const FIAT_DECIMALS: u8 = 2;
+pub const DEFAULT_MAX_PRICE_AGE: Duration = 300_000_000_000; // 5 minutes
+
+/**
+ * Price of the smallest unit of an asset (yoctoNEAR, token unit) in USD:
+ * usd_value = amount * multiplier / 10^decimals
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Price {
+    pub multiplier: U128,
+    pub decimals: u8
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AssetPrice {
+    pub asset_id: AssetId,
+    pub price: Price
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceData {
+    pub price: Price,
+    pub updated_at: Timestamp
+}
+
+/**
+ * Unit of an invoice amount
+ * - Fiat: USD cents, converted with the latest price of the paying token
+ * - Normalized: NORMALIZED_DECIMALS fixed point, converted with the paying token decimals
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PriceUnit {
+    Fiat,
+    Normalized
+}
+
+/**
+ * Order priced in fiat or normalized units, the token amount is computed at payment time
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Invoice {
+    pub order_id: OrderId,
+    pub merchant_id: AccountId,
+    pub amount: Balance,
+    pub unit: PriceUnit,
+    pub line_items: Vec<LineItem>,
+    pub created_at: Timestamp,
+    // Set when the invoice reserves product stock, see release_invoice
+    pub expires_at: Option<Timestamp>
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Configure the price feed
+     * - oracle_id: account allowed to push prices beside the owner (off-chain bot or oracle contract)
+     * - max_price_age: prices older than this (nanoseconds) are rejected at payment time
+     */
+    pub fn set_price_feed(&mut self, oracle_id: Option<AccountId>, max_price_age: Duration) {
+        self.assert_owner();
+        assert!(max_price_age > 0, "ERROR_INVALID_MAX_PRICE_AGE");
+        self.oracle_id = oracle_id;
+        self.max_price_age = max_price_age;
+    }
+
+    pub fn push_prices(&mut self, prices: Vec<AssetPrice>) {
+        let caller_id = env::predecessor_account_id();
+        assert!(
+            caller_id == self.owner_id || Some(&caller_id) == self.oracle_id.as_ref(),
+            "ERROR_NOT_PRICE_ORACLE"
+        );
+
+        let updated_at = env::block_timestamp();
+        for AssetPrice { asset_id, price } in prices {
+            assert!(price.multiplier.0 > 0, "ERROR_INVALID_PRICE");
+            self.prices.insert(&asset_id, &PriceData { price, updated_at });
+        }
+    }
+
+    /*
+     * Merchant creates an order priced in fiat cents or normalized units, paid later with NEAR or FT through pay_order/ft_on_transfer
+     */
+    pub fn create_invoice(
+        &mut self,
+        order_id: OrderId,
+        amount: U128,
+        unit: PriceUnit,
+        line_items: Option<Vec<LineItem>>
+    ) -> Invoice {
+        let merchant_id = self.assert_merchant();
+        assert!(amount.0 > 0, "ERROR_INVALID_AMOUNT");
+        assert!(self.orders.get(&order_id).is_none(), "ERROR_ORDER_ALREADY_EXISTS");
+        assert!(self.invoices.get(&order_id).is_none(), "ERROR_INVOICE_ALREADY_EXISTS");
+        let mut line_items = line_items.unwrap_or_default();
+        assert_line_items(&mut line_items, amount.0);
+
+        let created_at = env::block_timestamp();
+        let has_products = self.internal_take_stock(&merchant_id, &line_items);
+        let invoice = Invoice {
+            order_id: order_id.clone(),
+            merchant_id,
+            amount: amount.0,
+            unit,
+            line_items,
+            created_at,
+            expires_at: Some(created_at + self.reservation_ttl).filter(|_| has_products)
+        };
+        self.invoices.insert(&order_id, &invoice);
+
+        invoice
+    }
+
+    pub fn get_invoice(&self, order_id: OrderId) -> Invoice {
+        self.invoices.get(&order_id).expect("NOT_FOUND_INVOICE")
+    }
+
+    pub fn get_price(&self, asset_id: AssetId) -> Option<PriceData> {
+        self.prices.get(&asset_id)
+    }
+}
+
+impl EcommerceContract {
+    pub(crate) fn asset_id_of(&self, token: &Option<AccountId>) -> AssetId {
+        match token {
+            None => NEAR_ASSET_ID.to_owned(),
+            Some(token_id) => token_id.to_string()
+        }
+    }
+
+    /*
+     * Amount of the paying token for an invoice, with the rate used for fiat invoices
+     */
+    pub(crate) fn internal_invoice_amount(&self, invoice: &Invoice, token: &Option<AccountId>) -> (Balance, Option<Price>) {
+        match invoice.unit {
+            PriceUnit::Fiat => {
+                let rate = self.internal_get_fresh_price(&self.asset_id_of(token));
+                (fiat_to_amount(invoice.amount, &rate), Some(rate))
+            }
+            PriceUnit::Normalized => (normalized_to_amount(invoice.amount, self.token_decimals(token)), None)
+        }
+    }
+
+    pub(crate) fn internal_get_fresh_price(&self, asset_id: &AssetId) -> Price {
+        let data = self.prices.get(asset_id).expect("ERROR_PRICE_NOT_FOUND");
+        assert!(
+            env::block_timestamp() <= data.updated_at + self.max_price_age,
+            "ERROR_PRICE_STALE"
+        );
+        data.price
+    }
+}
+
+/// Token amount (smallest unit) needed to cover `fiat_amount` cents, rounded up.
+pub(crate) fn fiat_to_amount(fiat_amount: Balance, price: &Price) -> Balance {
+    let numerator = U256::from(fiat_amount) * U256::exp10(price.decimals as usize);
+    let denominator = U256::from(price.multiplier.0) * U256::exp10(FIAT_DECIMALS as usize);
+    let amount = (numerator + denominator - 1) / denominator;
+    assert!(amount <= U256::from(u128::MAX), "ERROR_AMOUNT_OVERFLOW");
+    amount.as_u128()
+}
@@ -0,0 +1,156 @@
+use near_sdk::{AccountId, Duration, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub type ProductKey = (AccountId, String);
+
+pub const DEFAULT_RESERVATION_TTL: Duration = 1_800_000_000_000; // 30 minutes
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProductPrice {
+    // None means NEAR
+    pub token: Option<AccountId>,
+    pub price: U128
+}
+
+/**
+ * Product of a merchant, matched with order line items by sku
+ * - stock is decremented when an order is paid (or reserved by an invoice) and restored on refund or expiry
+ * - tax_class: class of the merchant jurisdiction tax rates, DEFAULT_TAX_CLASS if None
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Product {
+    pub merchant_id: AccountId,
+    pub sku: String,
+    pub prices: Vec<ProductPrice>,
+    pub stock: u32,
+    pub is_active: bool,
+    pub tax_class: Option<String>
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    pub fn add_product(&mut self, sku: String, prices: Vec<ProductPrice>, stock: u32, is_active: bool) -> Product {
+        let merchant_id = self.assert_merchant();
+        let key = (merchant_id.clone(), sku.clone());
+        assert!(self.products.get(&key).is_none(), "ERROR_PRODUCT_ALREADY_EXISTS");
+        for price in prices.iter() {
+            self.payment_method_of(&price.token);
+        }
+
+        let product = Product { merchant_id, sku, prices, stock, is_active, tax_class: None };
+        self.products.insert(&key, &product);
+        product
+    }
+
+    /*
+     * Update the calling merchant's product, missing fields are unchanged
+     */
+    pub fn update_product(
+        &mut self,
+        sku: String,
+        prices: Option<Vec<ProductPrice>>,
+        stock: Option<u32>,
+        is_active: Option<bool>
+    ) -> Product {
+        let merchant_id = self.assert_merchant();
+        let mut product = self.get_product(merchant_id, sku);
+
+        if let Some(prices) = prices {
+            for price in prices.iter() {
+                self.payment_method_of(&price.token);
+            }
+            product.prices = prices;
+        }
+        if let Some(stock) = stock {
+            product.stock = stock;
+        }
+        if let Some(is_active) = is_active {
+            product.is_active = is_active;
+        }
+
+        self.products.insert(&(product.merchant_id.clone(), product.sku.clone()), &product);
+        product
+    }
+
+    /*
+     * Cancel an unpaid invoice and give its reserved stock back
+     * - The merchant can release at any time, anyone once the reservation expired
+     */
+    pub fn release_invoice(&mut self, order_id: OrderId) {
+        let invoice = self.get_invoice(order_id.clone());
+        assert!(self.orders.get(&order_id).is_none(), "ERROR_ORDER_ALREADY_PAID");
+        let is_expired = invoice.expires_at.is_some_and(|expires_at| env::block_timestamp() > expires_at);
+        assert!(
+            env::predecessor_account_id() == invoice.merchant_id || is_expired,
+            "ERROR_NOT_ALLOWED"
+        );
+
+        if invoice.expires_at.is_some() {
+            for line in invoice.line_items.iter() {
+                self.internal_restore_stock(&invoice.merchant_id, &line.sku, line.quantity);
+            }
+        }
+        self.invoices.remove(&order_id);
+    }
+
+    pub fn set_reservation_ttl(&mut self, reservation_ttl: Duration) {
+        self.assert_owner();
+        assert!(reservation_ttl > 0, "ERROR_INVALID_RESERVATION_TTL");
+        self.reservation_ttl = reservation_ttl;
+    }
+
+    pub fn get_product(&self, merchant_id: AccountId, sku: String) -> Product {
+        self.products.get(&(merchant_id, sku)).expect("NOT_FOUND_PRODUCT")
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Take stock for the line items matching the merchant's products, other lines are free-form
+     * - Return true if any product stock was taken
+     */
+    pub(crate) fn internal_take_stock(&mut self, merchant_id: &AccountId, line_items: &[LineItem]) -> bool {
+        let mut has_products = false;
+        for line in line_items.iter() {
+            let key = (merchant_id.clone(), line.sku.clone());
+            if let Some(mut product) = self.products.get(&key) {
+                assert!(product.is_active, "ERROR_PRODUCT_NOT_ACTIVE");
+                assert!(product.stock >= line.quantity, "ERROR_OUT_OF_STOCK");
+                product.stock -= line.quantity;
+                self.products.insert(&key, &product);
+                has_products = true;
+            }
+        }
+        has_products
+    }
+
+    pub(crate) fn internal_restore_stock(&mut self, merchant_id: &AccountId, sku: &str, quantity: u32) {
+        let key = (merchant_id.clone(), sku.to_owned());
+        if let Some(mut product) = self.products.get(&key) {
+            product.stock += quantity;
+            self.products.insert(&key, &product);
+        }
+    }
+
+    /*
+     * Buyers choose the line prices of ad-hoc orders, so they must match the product price of the paying token
+     * - Product lines are charged quantity * price, buyers cannot discount or tax them
+     * - The order amount is the sum of the lines, see assert_line_items
+     */
+    pub(crate) fn assert_product_prices(&self, merchant_id: &AccountId, line_items: &[LineItem], token: &Option<AccountId>) {
+        for line in line_items.iter() {
+            if let Some(product) = self.products.get(&(merchant_id.clone(), line.sku.clone())) {
+                let price = product.prices.iter()
+                    .find(|price| &price.token == token)
+                    .expect("ERROR_PRODUCT_PRICE_NOT_FOUND");
+                assert_eq!(line.unit_price, price.price, "ERROR_PRODUCT_PRICE_MISMATCH");
+                assert!(line.discount.0 == 0 && line.tax.0 == 0, "ERROR_PRODUCT_PRICE_MISMATCH");
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use near_sdk::{AccountId, Balance, Duration, Timestamp, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub const DEFAULT_QUOTE_TTL: Duration = 600_000_000_000; // 10 minutes
+
+/**
+ * Amount to pay for an invoiced order with a given token, valid until expires_at
+ * - Quotes are stored by the contract, paying with the quote id charges the quoted amount at the quoted rate
+ *   until the quote expires, whatever the price does in between
+ * - One quote per order and token is valid at a time, get_quote returns it again until it expires
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Quote {
+    pub quote_id: String,
+    pub order_id: OrderId,
+    pub token: Option<AccountId>,
+    pub amount: U128,
+    pub rate: Option<Price>,
+    pub expires_at: Timestamp
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Quote an unpaid invoiced order, token = None means NEAR
+     * - Pass the quote id to pay_order/ft_on_transfer before expires_at to be charged the quoted amount
+     */
+    pub fn get_quote(&mut self, order_id: OrderId, token: Option<AccountId>) -> Quote {
+        let invoice = self.get_invoice(order_id.clone());
+        assert!(self.orders.get(&order_id).map_or(true, |order| !order.is_completed), "ERROR_ORDER_ALREADY_PAID");
+        self.payment_method_of(&token);
+        let asset_id = self.asset_id_of(&token);
+        let key = (order_id.clone(), asset_id.clone());
+        if let Some(quote) = self.quotes.get(&key).filter(|quote| env::block_timestamp() <= quote.expires_at) {
+            return quote;
+        }
+
+        let (amount, rate) = self.internal_invoice_amount(&invoice, &token);
+        let expires_at = env::block_timestamp() + self.quote_ttl;
+        let quote = Quote {
+            quote_id: build_quote_id(&order_id, &asset_id, expires_at),
+            order_id,
+            token,
+            amount: U128(amount),
+            rate,
+            expires_at
+        };
+        self.quotes.insert(&key, &quote);
+        quote
+    }
+
+    pub fn set_quote_ttl(&mut self, quote_ttl: Duration) {
+        self.assert_owner();
+        assert!(quote_ttl > 0, "ERROR_INVALID_QUOTE_TTL");
+        self.quote_ttl = quote_ttl;
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Amount and rate of the quote an invoice payment was made with, the quote is used up by the payment
+     * - Reject quotes never issued by get_quote, of another order/token, or expired
+     */
+    pub(crate) fn internal_use_quote(&mut self, payment: &Payment, quote_id: &str) -> (Balance, Option<Price>) {
+        let key = (payment.order_id.clone(), self.asset_id_of(&payment.token_id));
+        let quote = self.quotes.get(&key)
+            .filter(|quote| quote.quote_id == quote_id)
+            .expect("ERROR_INVALID_QUOTE_ID");
+        assert!(env::block_timestamp() <= quote.expires_at, "ERROR_QUOTE_EXPIRED");
+        self.quotes.remove(&key);
+        (quote.amount.0, quote.rate)
+    }
+
+    /*
+     * Reject payments costing more than the payer's max amount
+     */
+    pub(crate) fn assert_max_amount(&self, payment: &Payment, amount: Balance) {
+        if let Some(max_amount) = payment.max_amount {
+            assert!(amount <= max_amount, "ERROR_SLIPPAGE_EXCEEDED");
+        }
+    }
+}
+
+fn build_quote_id(order_id: &OrderId, asset_id: &AssetId, expires_at: Timestamp) -> String {
+    let data = (env::current_account_id(), order_id, asset_id, expires_at).try_to_vec().unwrap();
+    near_sdk::bs58::encode(env::sha256(&data)).into_string()
+}
@@ -0,0 +1,263 @@
+use near_sdk::{AccountId, Balance, Gas, PromiseOrValue, PromiseResult, Timestamp, assert_one_yocto, env, ext_contract, near_bindgen, serde_json};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedSet;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Receipt token ids are the order ids
+pub type TokenId = OrderId;
+
+pub const NFT_METADATA_SPEC: &str = "nft-1.0.0";
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_NFT_TRANSFER_CALL: Gas = Gas(25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER.0);
+
+/**
+ * NEP-171 purchase receipt minted to the payer of each completed order
+ * - owner_id changes when the receipt is transferred, e.g. to resell a warranty
+ * - is_void is set when the order is fully refunded, void receipts cannot be transferred
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Receipt {
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub merchant_id: AccountId,
+    // Paid token, None for NEAR
+    pub token: Option<AccountId>,
+    pub amount: U128,
+    pub created_at: Timestamp,
+    pub is_void: bool
+}
+
+/**
+ * NEP-177 contract and token metadata
+ */
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NFTContractMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub icon: Option<String>,
+    pub base_uri: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<String>
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub media: Option<String>,
+    pub media_hash: Option<String>,
+    pub copies: Option<u64>,
+    pub issued_at: Option<String>,
+    pub expires_at: Option<String>,
+    pub starts_at: Option<String>,
+    pub updated_at: Option<String>,
+    // JSON of the receipt
+    pub extra: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<String>
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Token {
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub metadata: Option<TokenMetadata>
+}
+
+#[ext_contract(ext_nft_receiver)]
+pub trait NonFungibleTokenReceiver {
+    fn nft_on_transfer(&mut self, sender_id: AccountId, previous_owner_id: AccountId, token_id: TokenId, msg: String) -> PromiseOrValue<bool>;
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    #[payable]
+    pub fn nft_transfer(&mut self, receiver_id: AccountId, token_id: TokenId, approval_id: Option<u64>, memo: Option<String>) {
+        assert_one_yocto();
+        assert!(approval_id.is_none(), "ERROR_APPROVALS_NOT_SUPPORTED");
+        self.internal_transfer_receipt(&env::predecessor_account_id(), &receiver_id, &token_id, memo);
+    }
+
+    #[payable]
+    pub fn nft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        msg: String
+    ) -> PromiseOrValue<bool> {
+        assert_one_yocto();
+        assert!(approval_id.is_none(), "ERROR_APPROVALS_NOT_SUPPORTED");
+        let sender_id = env::predecessor_account_id();
+        self.internal_transfer_receipt(&sender_id, &receiver_id, &token_id, memo);
+
+        let promise = ext_nft_receiver::ext(receiver_id.clone())
+            .with_static_gas(env::prepaid_gas() - GAS_FOR_NFT_TRANSFER_CALL)
+            .nft_on_transfer(sender_id.clone(), sender_id.clone(), token_id.clone(), msg)
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                    .nft_resolve_transfer(sender_id, receiver_id, token_id, None)
+            );
+        PromiseOrValue::Promise(promise)
+    }
+
+    pub fn nft_token(&self, token_id: TokenId) -> Option<Token> {
+        self.receipts.get(&token_id).map(|receipt| receipt_token(&receipt))
+    }
+
+    pub fn nft_metadata(&self) -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_owned(),
+            name: "Payment Receipts".to_owned(),
+            symbol: "RECEIPT".to_owned(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None
+        }
+    }
+
+    pub fn nft_supply_for_owner(&self, account_id: AccountId) -> U128 {
+        U128(self.receipts_per_owner.get(&account_id).map_or(0, |token_ids| token_ids.len() as Balance))
+    }
+
+    pub fn nft_tokens_for_owner(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        let token_ids = match self.receipts_per_owner.get(&account_id) {
+            Some(token_ids) => token_ids,
+            None => return vec![]
+        };
+        token_ids.iter()
+            .skip(from_index.map_or(0, |from_index| from_index.0 as usize))
+            .take(limit.unwrap_or(token_ids.len()) as usize)
+            .map(|token_id| receipt_token(&self.receipts.get(&token_id).unwrap()))
+            .collect()
+    }
+}
+
+impl EcommerceContract {
+    pub(crate) fn internal_mint_receipt(&mut self, order: &Order) {
+        let receipt = Receipt {
+            token_id: order.order_id.clone(),
+            owner_id: order.payer_id.clone(),
+            merchant_id: order.merchant_id.clone(),
+            token: order.token_id.clone(),
+            amount: U128(order.amount),
+            created_at: env::block_timestamp(),
+            is_void: false
+        };
+        self.receipts.insert(&receipt.token_id, &receipt);
+        self.internal_add_receipt_to_owner(&receipt.owner_id, &receipt.token_id);
+
+        log_nft_event("nft_mint", serde_json::json!([{
+            "owner_id": receipt.owner_id,
+            "token_ids": [receipt.token_id]
+        }]));
+    }
+
+    pub(crate) fn internal_void_receipt(&mut self, order_id: &OrderId) {
+        if let Some(mut receipt) = self.receipts.get(order_id) {
+            receipt.is_void = true;
+            self.receipts.insert(order_id, &receipt);
+        }
+    }
+
+    /*
+     * Give the receipt back to its previous owner if the receiver returned true in nft_on_transfer
+     */
+    pub(crate) fn internal_nft_resolve_transfer(&mut self, previous_owner_id: AccountId, receiver_id: AccountId, token_id: TokenId) -> bool {
+        let must_revert = match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => serde_json::from_slice::<bool>(&value).unwrap_or(true),
+            PromiseResult::Failed => true
+        };
+        if !must_revert {
+            return true;
+        }
+
+        match self.receipts.get(&token_id) {
+            // the receiver may have transferred it already
+            Some(receipt) if receipt.owner_id == receiver_id => {
+                self.internal_transfer_receipt(&receiver_id, &previous_owner_id, &token_id, None);
+                false
+            }
+            _ => true
+        }
+    }
+
+    fn internal_transfer_receipt(&mut self, sender_id: &AccountId, receiver_id: &AccountId, token_id: &TokenId, memo: Option<String>) {
+        let mut receipt = self.receipts.get(token_id).expect("NOT_FOUND_RECEIPT");
+        assert_eq!(&receipt.owner_id, sender_id, "ERROR_NOT_RECEIPT_OWNER");
+        assert_ne!(sender_id, receiver_id, "ERROR_SAME_RECEIVER");
+        assert!(!receipt.is_void, "ERROR_RECEIPT_VOID");
+
+        let mut token_ids = self.receipts_per_owner.get(sender_id).unwrap();
+        token_ids.remove(token_id);
+        if token_ids.is_empty() {
+            self.receipts_per_owner.remove(sender_id);
+        } else {
+            self.receipts_per_owner.insert(sender_id, &token_ids);
+        }
+        self.internal_add_receipt_to_owner(receiver_id, token_id);
+
+        receipt.owner_id = receiver_id.clone();
+        self.receipts.insert(token_id, &receipt);
+
+        log_nft_event("nft_transfer", serde_json::json!([{
+            "old_owner_id": sender_id,
+            "new_owner_id": receiver_id,
+            "token_ids": [token_id],
+            "memo": memo
+        }]));
+    }
+
+    fn internal_add_receipt_to_owner(&mut self, owner_id: &AccountId, token_id: &TokenId) {
+        let mut token_ids = self.receipts_per_owner.get(owner_id).unwrap_or_else(|| {
+            UnorderedSet::new(StorageKey::ReceiptsPerOwner { account_hash: env::sha256_array(owner_id.as_bytes()) })
+        });
+        token_ids.insert(token_id);
+        self.receipts_per_owner.insert(owner_id, &token_ids);
+    }
+}
+
+fn receipt_token(receipt: &Receipt) -> Token {
+    Token {
+        token_id: receipt.token_id.clone(),
+        owner_id: receipt.owner_id.clone(),
+        metadata: Some(TokenMetadata {
+            title: Some(format!("Receipt of order {}", receipt.token_id)),
+            description: Some(format!("Purchase from {}", receipt.merchant_id)),
+            media: None,
+            media_hash: None,
+            copies: Some(1),
+            issued_at: Some((receipt.created_at / 1_000_000).to_string()),
+            expires_at: None,
+            starts_at: None,
+            updated_at: None,
+            extra: Some(serde_json::to_string(receipt).unwrap()),
+            reference: None,
+            reference_hash: None
+        })
+    }
+}
+
+/*
+ * NEP-297 event of the NEP-171 standard
+ */
+fn log_nft_event(event: &str, data: serde_json::Value) {
+    env::log_str(&format!("EVENT_JSON:{}", serde_json::json!({
+        "standard": "nep171",
+        "version": "1.0.0",
+        "event": event,
+        "data": data
+    })));
+}
+
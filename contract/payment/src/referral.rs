@@ -0,0 +1,50 @@
+use near_sdk::{AccountId, Balance, near_bindgen};
+
+use crate::*;
+
+const MAX_COMMISSION_BASIS_POINTS: u16 = 10_000;
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Commission of the calling merchant paid to referrers, in basis points of the order amount before tax
+     * - 0 disables commissions, orders paid with a referrer are then paid as usual
+     */
+    pub fn set_commission_rate(&mut self, basis_points: u16) {
+        let mut merchant = self.get_merchant(self.assert_merchant());
+        assert!(basis_points <= MAX_COMMISSION_BASIS_POINTS, "ERROR_INVALID_COMMISSION_RATE");
+        merchant.commission_basis_points = basis_points;
+        self.merchants.insert(&merchant.merchant_id, &merchant);
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Commission of the referrer of a payment on the order amount before tax
+     * - Campaign payments settle in full to the merchant and NFT payments hold no fungible funds, so they pay no commission
+     * - Orders priced by their payer pay no commission, the payer could name another account of their own as referrer
+     */
+    pub(crate) fn internal_compute_commission(&self, payment: &Payment, merchant_id: &AccountId, amount: Balance, price_source: PriceSource) -> Balance {
+        let referrer = match &payment.referrer {
+            Some(referrer) => referrer,
+            None => return 0
+        };
+        assert!(referrer != &payment.payer_id && referrer != merchant_id, "ERROR_SELF_REFERRAL");
+        if payment.campaign_id.is_some() || payment.nft_payment.is_some() || price_source == PriceSource::Payer {
+            return 0;
+        }
+
+        let basis_points = self.get_merchant(merchant_id.clone()).commission_basis_points;
+        (U256::from(amount) * U256::from(basis_points) / U256::from(MAX_COMMISSION_BASIS_POINTS)).as_u128()
+    }
+
+    /*
+     * Share of the order commission in an amount paid or refunded for the order
+     */
+    pub(crate) fn order_commission_share(order: &Order, amount: Balance) -> Balance {
+        if order.commission == 0 || order.amount == 0 {
+            return 0;
+        }
+        (U256::from(order.commission) * U256::from(amount) / U256::from(order.amount)).as_u128()
+    }
+}
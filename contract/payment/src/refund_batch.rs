@@ -0,0 +1,195 @@
+use near_sdk::{AccountId, Balance, Promise, PromiseResult, env, near_bindgen};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub const REFUND_BATCH_CALLBACK_GAS: Gas = Gas(5_000_000_000_000);
+// Gas of the callback for each refunded order, plus REWARD_GAS for orders with a reward to claw back
+pub const REFUND_BATCH_ORDER_GAS: Gas = Gas(2_000_000_000_000);
+// Gas kept to finish refund_batch itself
+const REFUND_BATCH_RESERVED_GAS: Gas = Gas(20_000_000_000_000);
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RefundStatus {
+    Refunded,
+    Failed,
+    // Not refundable: unknown, unpaid or already refunded order, or the merchant balance is too low
+    Skipped,
+    // Above the multisig threshold, a request was created instead
+    PendingApproval
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RefundOutcome {
+    pub order_id: OrderId,
+    pub status: RefundStatus
+}
+
+/**
+ * Result of refund_batch, call it again from next_index until it is None
+ */
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RefundBatchReport {
+    pub outcomes: Vec<RefundOutcome>,
+    pub next_index: Option<u32>
+}
+
+/**
+ * Orders refunded with a single transfer to their payer
+ */
+struct RefundGroup {
+    token: Option<AccountId>,
+    payer_id: AccountId,
+    amount: Balance,
+    order_ids: Vec<OrderId>
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Refund many orders at once, e.g. when an event is cancelled
+     * - Refunds to the same payer in the same token are grouped into one transfer
+     * - Stops before the prepaid gas runs out, the report gives the index to resume from
+     */
+    pub fn refund_batch(&mut self, order_ids: Vec<OrderId>, from_index: Option<u32>) -> PromiseOrValue<RefundBatchReport> {
+        self.assert_owner();
+
+        let mut outcomes = vec![];
+        let mut groups: Vec<RefundGroup> = vec![];
+        let mut next_index = None;
+        let mut transfer_gas = Gas(0);
+        let mut callback_gas = REFUND_BATCH_CALLBACK_GAS;
+
+        for (index, order_id) in order_ids.iter().enumerate().skip(from_index.unwrap_or(0) as usize) {
+            let order = match self.orders.get(order_id) {
+                Some(order) if self.is_refundable(&order) => order,
+                _ => {
+                    outcomes.push(RefundOutcome { order_id: order_id.clone(), status: RefundStatus::Skipped });
+                    continue;
+                }
+            };
+
+            let group_index = groups.iter().position(|group| group.token == order.token_id && group.payer_id == order.payer_id);
+            let new_transfer_gas = match (group_index, &order.token_id) {
+                (None, Some(_)) => TRANSFER_GAS,
+                _ => Gas(0)
+            };
+            let order_callback_gas = refund_batch_order_gas(&order);
+            let required_gas = env::used_gas() + REFUND_BATCH_RESERVED_GAS + transfer_gas + new_transfer_gas
+                + callback_gas + order_callback_gas;
+            if required_gas > env::prepaid_gas() {
+                next_index = Some(index as u32);
+                break;
+            }
+
+            let amount = order.amount - order.refunded_amount;
+            if self.requires_multisig(&order.token_id, amount) {
+                self.internal_add_request(MultisigAction::Refund { order_id: order_id.clone() });
+                outcomes.push(RefundOutcome { order_id: order_id.clone(), status: RefundStatus::PendingApproval });
+                continue;
+            }
+
+            let mut order = order;
+            order.is_refund = true;
+            self.internal_refund_gift_card(&mut order);
+            self.orders.insert(order_id, &order);
+            self.internal_debit_merchant(&order, amount);
+
+            if amount == 0 {
+                self.internal_on_order_refunded(order_id.clone());
+                outcomes.push(RefundOutcome { order_id: order_id.clone(), status: RefundStatus::Refunded });
+                continue;
+            }
+
+            match group_index {
+                Some(group_index) => {
+                    groups[group_index].amount += amount;
+                    groups[group_index].order_ids.push(order_id.clone());
+                }
+                None => groups.push(RefundGroup {
+                    token: order.token_id,
+                    payer_id: order.payer_id,
+                    amount,
+                    order_ids: vec![order_id.clone()]
+                })
+            }
+            transfer_gas += new_transfer_gas;
+            callback_gas += order_callback_gas;
+        }
+
+        if groups.is_empty() {
+            return PromiseOrValue::Value(RefundBatchReport { outcomes, next_index });
+        }
+
+        let promise = groups.iter()
+            .map(|group| self.internal_transfer(group.token.clone(), group.payer_id.clone(), group.amount, "Refund orders from payment contract"))
+            .reduce(Promise::and)
+            .unwrap()
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_attached_deposit(0)
+                    .with_static_gas(callback_gas)
+                    .on_refund_batch(groups.into_iter().map(|group| group.order_ids).collect(), outcomes, next_index)
+            );
+        PromiseOrValue::Promise(promise)
+    }
+}
+
+impl EcommerceContract {
+    pub(crate) fn internal_on_refund_batch(
+        &mut self,
+        groups: Vec<Vec<OrderId>>,
+        mut outcomes: Vec<RefundOutcome>,
+        next_index: Option<u32>
+    ) -> RefundBatchReport {
+        assert_eq!(env::promise_results_count(), groups.len() as u64, "ERROR_TOO_MANY_RESULTS");
+        for (index, order_ids) in groups.into_iter().enumerate() {
+            let is_refunded = match env::promise_result(index as u64) {
+                PromiseResult::NotReady => unreachable!(),
+                PromiseResult::Successful(_value) => true,
+                PromiseResult::Failed => false
+            };
+            for order_id in order_ids {
+                if is_refunded {
+                    self.internal_on_order_refunded(order_id.clone());
+                } else {
+                    // update refund status, the merchant holds the amount again
+                    let mut order = self.get_order(order_id.clone());
+                    order.is_refund = false;
+                    self.orders.insert(&order_id, &order);
+                    self.internal_credit_merchant(&order, order.amount - order.refunded_amount);
+                }
+                let status = if is_refunded { RefundStatus::Refunded } else { RefundStatus::Failed };
+                outcomes.push(RefundOutcome { order_id, status });
+            }
+        }
+        RefundBatchReport { outcomes, next_index }
+    }
+
+    /*
+     * Same checks as refund, without panicking so one order does not fail the batch
+     * - NFT payments are refunded one by one with refund
+     */
+    fn is_refundable(&self, order: &Order) -> bool {
+        let is_campaign_refundable = match &order.campaign_id {
+            Some(campaign_id) => self.get_campaign(campaign_id.clone()).status == CampaignStatus::Failed,
+            None => true
+        };
+        order.is_completed && !order.is_refund && order.issued_gift_card.is_none() && order.nft_payment.is_none()
+            && is_campaign_refundable && self.can_debit_merchant(order, order.amount - order.refunded_amount)
+    }
+}
+
+/*
+ * Gas of the batch callback for one order, its reward burn is scheduled from the callback
+ */
+pub(crate) fn refund_batch_order_gas(order: &Order) -> Gas {
+    if order.reward_token_id.is_some() && order.reward_amount > 0 {
+        REFUND_BATCH_ORDER_GAS + REWARD_GAS
+    } else {
+        REFUND_BATCH_ORDER_GAS
+    }
+}
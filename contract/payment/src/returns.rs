@@ -0,0 +1,236 @@
+use near_sdk::{AccountId, PromiseOrValue, PromiseResult, Timestamp, env, log, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ReturnStatus {
+    Requested,
+    Approved,
+    Rejected,
+    // Item received by the merchant, refund in progress
+    Received,
+    Refunded
+}
+
+/**
+ * Return merchandise authorization requested by the payer of an order
+ * - items: line items to return, None returns the whole order
+ * - restocking_fee: kept by the merchant from the refund, in the paid token
+ * - refund_amount: amount of the order refunded, set when the merchant receives the item
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReturnRequest {
+    pub order_id: OrderId,
+    pub payer_id: AccountId,
+    pub merchant_id: AccountId,
+    pub reason: String,
+    pub items: Option<Vec<LineItemRefund>>,
+    pub status: ReturnStatus,
+    pub restocking_fee: U128,
+    pub refund_amount: U128,
+    pub created_at: Timestamp,
+    pub updated_at: Timestamp
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Ask the merchant to take back an order or some of its line items
+     * - A new request can be made once the previous one was rejected or refunded
+     */
+    pub fn request_return(&mut self, order_id: OrderId, reason: String, items: Option<Vec<LineItemRefund>>) -> ReturnRequest {
+        let order = self.get_order(order_id.clone());
+        assert_eq!(env::predecessor_account_id(), order.payer_id, "ERROR_NOT_ALLOWED");
+        assert!(order.is_completed && !order.is_refund, "ERROR_ORDER_NOT_REFUNDABLE");
+        assert!(order.issued_gift_card.is_none(), "ERROR_GIFT_CARD_NOT_REFUNDABLE");
+        self.assert_campaign_refundable(&order);
+        Self::assert_nft_refundable(&order);
+        assert!(!reason.is_empty(), "ERROR_REASON_REQUIRED");
+        if let Some(items) = &items {
+            assert!(order.nft_payment.is_none(), "ERROR_NFT_ORDER_NOT_SPLITTABLE");
+            line_items_refund_amount(&order, items);
+        }
+        if let Some(previous) = self.returns.get(&order_id) {
+            assert!(
+                matches!(previous.status, ReturnStatus::Rejected | ReturnStatus::Refunded),
+                "ERROR_RETURN_ALREADY_REQUESTED"
+            );
+        }
+
+        let now = env::block_timestamp();
+        let return_request = ReturnRequest {
+            order_id: order_id.clone(),
+            payer_id: order.payer_id,
+            merchant_id: order.merchant_id,
+            reason,
+            items,
+            status: ReturnStatus::Requested,
+            restocking_fee: U128(0),
+            refund_amount: U128(0),
+            created_at: now,
+            updated_at: now
+        };
+        self.returns.insert(&order_id, &return_request);
+        return_request
+    }
+
+    pub fn approve_return(&mut self, order_id: OrderId, restocking_fee: Option<U128>) {
+        let mut return_request = self.internal_merchant_return(&order_id, ReturnStatus::Requested);
+        let restocking_fee = restocking_fee.unwrap_or(U128(0));
+        assert!(
+            restocking_fee.0 == 0 || self.get_order(order_id.clone()).nft_payment.is_none(),
+            "ERROR_NFT_ORDER_NOT_SPLITTABLE"
+        );
+        return_request.restocking_fee = restocking_fee;
+        self.internal_update_return(return_request, ReturnStatus::Approved);
+    }
+
+    pub fn reject_return(&mut self, order_id: OrderId) {
+        let return_request = self.internal_merchant_return(&order_id, ReturnStatus::Requested);
+        self.internal_update_return(return_request, ReturnStatus::Rejected);
+    }
+
+    /*
+     * Mark the returned item as received, refunding the payer minus the restocking fee
+     * - Payouts above the multisig threshold only create a pending request
+     */
+    pub fn receive_return(&mut self, order_id: OrderId) -> PromiseOrValue<U128> {
+        let return_request = self.internal_merchant_return(&order_id, ReturnStatus::Approved);
+        let order = self.get_order(order_id.clone());
+        let refund_amount = match &return_request.items {
+            Some(items) => line_items_refund_amount(&order, items),
+            None => order.amount - order.refunded_amount
+        };
+
+        if self.requires_multisig(&order.token_id, refund_amount.saturating_sub(return_request.restocking_fee.0)) {
+            self.internal_add_request(MultisigAction::ReceiveReturn { order_id });
+            return PromiseOrValue::Value(U128(0));
+        }
+
+        self.internal_receive_return(order_id)
+    }
+
+    pub fn get_return(&self, order_id: OrderId) -> ReturnRequest {
+        self.returns.get(&order_id).expect("NOT_FOUND_RETURN")
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Finish the refund of a received return, or put it back to Approved if the transfer failed
+     */
+    pub(crate) fn internal_on_refund_return(&mut self, order_id: OrderId) -> U128 {
+        assert_eq!(env::promise_results_count(), 1, "ERROR_TOO_MANY_RESULTS");
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_value) => {
+                self.internal_on_return_refunded(order_id);
+                U128(0)
+            }
+            PromiseResult::Failed => {
+                let return_request = self.get_return(order_id.clone());
+                let refund_amount = return_request.refund_amount.0;
+                let payout = refund_amount - return_request.restocking_fee.0;
+
+                let mut order = self.get_order(order_id.clone());
+                match &return_request.items {
+                    Some(items) => {
+                        for refund in items.iter() {
+                            let line = order.line_items.iter_mut().find(|line| line.sku == refund.sku).unwrap();
+                            line.refunded_quantity -= refund.quantity;
+                        }
+                        order.refunded_amount -= refund_amount;
+                    }
+                    None => order.is_refund = false
+                }
+                self.orders.insert(&order_id, &order);
+                self.internal_credit_merchant(&order, payout);
+                self.internal_update_return(return_request, ReturnStatus::Approved);
+                log!("Failed to refund the return of order {}", order_id);
+                U128(payout)
+            }
+        }
+    }
+
+    fn internal_on_return_refunded(&mut self, order_id: OrderId) {
+        let return_request = self.get_return(order_id.clone());
+        match &return_request.items {
+            Some(items) => self.internal_on_line_items_refunded(order_id, items, return_request.refund_amount.0),
+            None => self.internal_on_order_refunded(order_id)
+        }
+        self.internal_update_return(return_request, ReturnStatus::Refunded);
+    }
+
+    /*
+     * Return of an order of the calling merchant in the expected status
+     */
+    pub(crate) fn internal_receive_return(&mut self, order_id: OrderId) -> PromiseOrValue<U128> {
+        let mut return_request = self.get_return(order_id.clone());
+        assert_eq!(return_request.status, ReturnStatus::Approved, "ERROR_INVALID_RETURN_STATUS");
+        let mut order = self.get_order(order_id.clone());
+
+        assert!(order.is_completed && !order.is_refund, "ERROR_ORDER_NOT_REFUNDABLE");
+        Self::assert_nft_refundable(&order);
+
+        let refund_amount = match &return_request.items {
+            Some(items) => {
+                let amount = line_items_refund_amount(&order, items);
+                for refund in items.iter() {
+                    let line = order.line_items.iter_mut().find(|line| line.sku == refund.sku).unwrap();
+                    line.refunded_quantity += refund.quantity;
+                }
+                order.refunded_amount += amount;
+                amount
+            }
+            None => {
+                order.is_refund = true;
+                order.amount - order.refunded_amount
+            }
+        };
+        assert!(return_request.restocking_fee.0 <= refund_amount, "ERROR_RESTOCKING_FEE_TOO_HIGH");
+        let payout = refund_amount - return_request.restocking_fee.0;
+
+        // the gift card share goes back to the card as store credit
+        self.internal_refund_gift_card(&mut order);
+        if matches!(order.fulfilment.last().map(|update| update.status), Some(FulfilmentStatus::Shipped | FulfilmentStatus::Delivered)) {
+            add_fulfilment_update(&mut order, FulfilmentStatus::Returned, None);
+        }
+        self.orders.insert(&order_id, &order);
+        self.internal_debit_merchant(&order, payout);
+
+        return_request.refund_amount = U128(refund_amount);
+        self.internal_update_return(return_request, ReturnStatus::Received);
+
+        let promise = match order.nft_payment {
+            Some(nft_payment) => self.internal_transfer_nft(nft_payment, order.payer_id, "Return from payment contract"),
+            None if payout > 0 => self.internal_transfer(order.token_id, order.payer_id, payout, "Return from payment contract"),
+            None => {
+                self.internal_on_return_refunded(order_id);
+                return PromiseOrValue::Value(U128(0));
+            }
+        };
+        PromiseOrValue::Promise(promise.then(
+            ext_self::ext(env::current_account_id())
+                .with_attached_deposit(0)
+                .with_static_gas(REFUND_CALLBACK_GAS)
+                .on_refund_return(order_id)
+        ))
+    }
+
+    fn internal_merchant_return(&self, order_id: &OrderId, status: ReturnStatus) -> ReturnRequest {
+        let return_request = self.get_return(order_id.clone());
+        assert_eq!(env::predecessor_account_id(), return_request.merchant_id, "ERROR_NOT_ALLOWED");
+        assert_eq!(return_request.status, status, "ERROR_INVALID_RETURN_STATUS");
+        return_request
+    }
+
+    fn internal_update_return(&mut self, mut return_request: ReturnRequest, status: ReturnStatus) {
+        return_request.status = status;
+        return_request.updated_at = env::block_timestamp();
+        self.returns.insert(&return_request.order_id, &return_request);
+    }
+}
@@ -0,0 +1,112 @@
+use near_sdk::{AccountId, Timestamp, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+const MAX_RATING: u8 = 5;
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReviewReply {
+    pub content_hash: Base58CryptoHash,
+    pub created_at: Timestamp
+}
+
+/**
+ * Verified purchase review, one per order, posted by the payer
+ * - content_hash: hash of the review text stored off-chain
+ * - reply: single reply of the merchant
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Review {
+    pub order_id: OrderId,
+    pub reviewer_id: AccountId,
+    pub merchant_id: AccountId,
+    pub rating: u8,
+    pub content_hash: Base58CryptoHash,
+    pub created_at: Timestamp,
+    pub reply: Option<ReviewReply>
+}
+
+/**
+ * Aggregate rating, the average is rating_total / num_reviews
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RatingSummary {
+    pub num_reviews: u64,
+    pub rating_total: u64
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Review a completed order that was not refunded, counted for the merchant and the products of the order
+     * - Only paid orders priced by the merchant (invoice, signed order or installment plan) whose delivery was confirmed,
+     *   so ratings can't be farmed with free ad-hoc orders
+     */
+    pub fn post_review(&mut self, order_id: OrderId, rating: u8, content_hash: Base58CryptoHash) -> Review {
+        let order = self.get_order(order_id.clone());
+        assert_eq!(env::predecessor_account_id(), order.payer_id, "ERROR_NOT_ALLOWED");
+        assert!(order.is_completed && !order.is_refund, "ERROR_ORDER_NOT_REVIEWABLE");
+        assert!(order.amount > 0 && order.price_source != PriceSource::Payer, "ERROR_ORDER_NOT_REVIEWABLE");
+        assert!(order.delivery_confirmed_at.is_some(), "ERROR_DELIVERY_NOT_CONFIRMED");
+        assert!(self.reviews.get(&order_id).is_none(), "ERROR_REVIEW_ALREADY_EXISTS");
+        assert!((1..=MAX_RATING).contains(&rating), "ERROR_INVALID_RATING");
+
+        let review = Review {
+            order_id: order_id.clone(),
+            reviewer_id: order.payer_id,
+            merchant_id: order.merchant_id.clone(),
+            rating,
+            content_hash,
+            created_at: env::block_timestamp(),
+            reply: None
+        };
+        self.reviews.insert(&order_id, &review);
+
+        let mut merchant_rating = self.merchant_ratings.get(&order.merchant_id).unwrap_or_default();
+        merchant_rating.add(rating);
+        self.merchant_ratings.insert(&order.merchant_id, &merchant_rating);
+
+        for line in order.line_items.iter() {
+            let key = (order.merchant_id.clone(), line.sku.clone());
+            if self.products.get(&key).is_some() {
+                let mut product_rating = self.product_ratings.get(&key).unwrap_or_default();
+                product_rating.add(rating);
+                self.product_ratings.insert(&key, &product_rating);
+            }
+        }
+        review
+    }
+
+    pub fn reply_to_review(&mut self, order_id: OrderId, content_hash: Base58CryptoHash) {
+        let mut review = self.get_review(order_id.clone());
+        assert_eq!(env::predecessor_account_id(), review.merchant_id, "ERROR_NOT_ALLOWED");
+        assert!(review.reply.is_none(), "ERROR_REPLY_ALREADY_EXISTS");
+        review.reply = Some(ReviewReply { content_hash, created_at: env::block_timestamp() });
+        self.reviews.insert(&order_id, &review);
+    }
+
+    pub fn get_review(&self, order_id: OrderId) -> Review {
+        self.reviews.get(&order_id).expect("NOT_FOUND_REVIEW")
+    }
+
+    pub fn get_merchant_rating(&self, merchant_id: AccountId) -> RatingSummary {
+        self.merchant_ratings.get(&merchant_id).unwrap_or_default()
+    }
+
+    pub fn get_product_rating(&self, merchant_id: AccountId, sku: String) -> RatingSummary {
+        self.product_ratings.get(&(merchant_id, sku)).unwrap_or_default()
+    }
+}
+
+impl RatingSummary {
+    fn add(&mut self, rating: u8) {
+        self.num_reviews += 1;
+        self.rating_total += rating as u64;
+    }
+}
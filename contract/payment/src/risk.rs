@@ -0,0 +1,143 @@
+use near_sdk::{AccountId, Balance, Duration, Timestamp, env, near_bindgen};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub const DAY: Duration = 24 * 60 * 60 * 1_000_000_000;
+
+/**
+ * Limits of each payer in one token (None for NEAR), in the smallest unit of the token
+ * - max_order_amount: largest amount charged for one order or installment
+ * - daily_volume_cap: total charged per UTC day
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RiskLimits {
+    pub max_order_amount: Option<U128>,
+    pub daily_volume_cap: Option<U128>
+}
+
+/**
+ * At most max_orders new orders per payer in any period
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VelocityLimit {
+    pub max_orders: u32,
+    pub period: Duration
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PayerActivity {
+    // UTC day of daily_volumes
+    pub day: u64,
+    pub daily_volumes: Vec<(Option<AccountId>, U128)>,
+    // Creation time of the latest orders, kept for the velocity limit
+    pub recent_orders: Vec<Timestamp>
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Set or remove (None) the limits of a token
+     */
+    pub fn set_risk_limits(&mut self, token: Option<AccountId>, limits: Option<RiskLimits>) {
+        self.assert_owner();
+        self.payment_method_of(&token);
+        match limits {
+            Some(limits) => self.risk_limits.insert(&token, &limits),
+            None => self.risk_limits.remove(&token)
+        };
+    }
+
+    pub fn set_velocity_limit(&mut self, velocity_limit: Option<VelocityLimit>) {
+        self.assert_owner();
+        if let Some(velocity_limit) = &velocity_limit {
+            assert!(velocity_limit.max_orders > 0 && velocity_limit.period > 0, "ERROR_INVALID_VELOCITY_LIMIT");
+        }
+        self.velocity_limit = velocity_limit;
+    }
+
+    /*
+     * Payments of denied accounts are rejected and returned
+     */
+    pub fn deny_account(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        self.denied_accounts.insert(&account_id);
+    }
+
+    pub fn allow_account(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        self.denied_accounts.remove(&account_id);
+    }
+
+    pub fn is_denied_account(&self, account_id: AccountId) -> bool {
+        self.denied_accounts.contains(&account_id)
+    }
+
+    pub fn get_risk_limits(&self, token: Option<AccountId>) -> Option<RiskLimits> {
+        self.risk_limits.get(&token)
+    }
+
+    pub fn get_velocity_limit(&self) -> Option<VelocityLimit> {
+        self.velocity_limit.clone()
+    }
+
+    pub fn get_payer_activity(&self, account_id: AccountId) -> Option<PayerActivity> {
+        self.payer_activities.get(&account_id)
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Check the risk rules before charging a payer and record the payment
+     * - is_new_order: installments of an existing order do not count toward the velocity limit
+     */
+    pub(crate) fn internal_check_risk(&mut self, payer_id: &AccountId, token: &Option<AccountId>, amount: Balance, is_new_order: bool) {
+        assert!(!self.denied_accounts.contains(payer_id), "ERROR_ACCOUNT_DENIED");
+
+        let now = env::block_timestamp();
+        let day = now / DAY;
+        let mut activity = self.payer_activities.get(payer_id).unwrap_or(PayerActivity {
+            day,
+            daily_volumes: vec![],
+            recent_orders: vec![]
+        });
+        if activity.day != day {
+            activity.day = day;
+            activity.daily_volumes.clear();
+        }
+
+        let daily_volume = match activity.daily_volumes.iter_mut().find(|(volume_token, _)| volume_token == token) {
+            Some((_, volume)) => volume,
+            None => {
+                activity.daily_volumes.push((token.clone(), U128(0)));
+                &mut activity.daily_volumes.last_mut().unwrap().1
+            }
+        };
+        *daily_volume = U128(daily_volume.0 + amount);
+
+        if let Some(limits) = self.risk_limits.get(token) {
+            if let Some(max_order_amount) = limits.max_order_amount {
+                assert!(amount <= max_order_amount.0, "ERROR_ORDER_AMOUNT_LIMIT");
+            }
+            if let Some(daily_volume_cap) = limits.daily_volume_cap {
+                assert!(daily_volume.0 <= daily_volume_cap.0, "ERROR_DAILY_VOLUME_LIMIT");
+            }
+        }
+
+        if is_new_order {
+            activity.recent_orders.push(now);
+            if let Some(velocity_limit) = &self.velocity_limit {
+                activity.recent_orders.retain(|created_at| now < created_at + velocity_limit.period);
+                assert!(activity.recent_orders.len() <= velocity_limit.max_orders as usize, "ERROR_VELOCITY_LIMIT");
+            } else {
+                activity.recent_orders.clear();
+            }
+        }
+
+        self.payer_activities.insert(payer_id, &activity);
+    }
+}
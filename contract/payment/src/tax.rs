@@ -0,0 +1,166 @@
+use near_sdk::{AccountId, Balance, PromiseResult, env, log, near_bindgen};
+
+use crate::*;
+
+pub const DEFAULT_TAX_CLASS: &str = "standard";
+
+const MAX_TAX_BASIS_POINTS: u16 = 10_000;
+
+pub type TaxRateKey = (String, String);
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Set or remove (None) the tax rate of a product class in a jurisdiction, e.g. ("DE", "standard") = 1900
+     */
+    pub fn set_tax_rate(&mut self, jurisdiction: String, tax_class: String, basis_points: Option<u16>) {
+        self.assert_owner();
+        match basis_points {
+            Some(basis_points) => {
+                assert!(basis_points > 0 && basis_points <= MAX_TAX_BASIS_POINTS, "ERROR_INVALID_TAX_RATE");
+                self.tax_rates.insert(&(jurisdiction, tax_class), &basis_points);
+            }
+            None => {
+                self.tax_rates.remove(&(jurisdiction, tax_class));
+            }
+        }
+    }
+
+    pub fn get_tax_rate(&self, jurisdiction: String, tax_class: String) -> Option<u16> {
+        self.tax_rates.get(&(jurisdiction, tax_class))
+    }
+
+    /*
+     * Orders of merchants without a jurisdiction are not taxed
+     */
+    pub fn set_merchant_jurisdiction(&mut self, merchant_id: AccountId, jurisdiction: Option<String>) {
+        self.assert_owner();
+        let mut merchant = self.get_merchant(merchant_id);
+        merchant.jurisdiction = jurisdiction;
+        self.merchants.insert(&merchant.merchant_id, &merchant);
+    }
+
+    /*
+     * Tax class of a product of the calling merchant, DEFAULT_TAX_CLASS if None
+     */
+    pub fn set_product_tax_class(&mut self, sku: String, tax_class: Option<String>) -> Product {
+        let merchant_id = self.assert_merchant();
+        let mut product = self.get_product(merchant_id, sku);
+        product.tax_class = tax_class;
+        self.products.insert(&(product.merchant_id.clone(), product.sku.clone()), &product);
+        product
+    }
+
+    pub fn get_tax_balance(&self, jurisdiction: String, token: Option<AccountId>) -> U128 {
+        U128(self.tax_balances.get(&(jurisdiction, token)).unwrap_or(0))
+    }
+
+    /*
+     * Send collected tax of a jurisdiction to the tax authority account
+     * - Withdrawals above the multisig threshold only create a pending request
+     */
+    pub fn withdraw_tax(&mut self, jurisdiction: String, token: Option<AccountId>, receiver_id: AccountId, amount: U128) -> PromiseOrValue<U128> {
+        self.assert_owner();
+        assert!(amount.0 > 0, "ERROR_INVALID_AMOUNT");
+
+        if self.requires_multisig(&token, amount.0) {
+            self.internal_add_request(MultisigAction::WithdrawTax { jurisdiction, token, receiver_id, amount });
+            return PromiseOrValue::Value(U128(0));
+        }
+
+        self.internal_withdraw_tax(jurisdiction, token, receiver_id, amount)
+    }
+}
+
+impl EcommerceContract {
+    /*
+     * Tax included in the amount paid for an order, prices are tax inclusive
+     * - The amount is split by line totals, each line taxed at the rate of its product class
+     * - Orders without line items are taxed at the DEFAULT_TAX_CLASS rate
+     */
+    pub(crate) fn internal_compute_tax(&self, jurisdiction: &str, merchant_id: &AccountId, line_items: &[LineItem], amount: Balance) -> Balance {
+        let items_total: Balance = line_items.iter().map(|line| line.total()).sum();
+        let tax = if items_total == 0 {
+            self.internal_included_tax(jurisdiction, DEFAULT_TAX_CLASS, U256::from(amount))
+        } else {
+            line_items.iter().fold(U256::zero(), |tax, line| {
+                let tax_class = self.products.get(&(merchant_id.clone(), line.sku.clone()))
+                    .and_then(|product| product.tax_class)
+                    .unwrap_or_else(|| DEFAULT_TAX_CLASS.to_owned());
+                let line_amount = U256::from(amount) * U256::from(line.total()) / U256::from(items_total);
+                tax + self.internal_included_tax(jurisdiction, &tax_class, line_amount)
+            })
+        };
+        tax.as_u128()
+    }
+
+    fn internal_included_tax(&self, jurisdiction: &str, tax_class: &str, amount: U256) -> U256 {
+        match self.tax_rates.get(&(jurisdiction.to_owned(), tax_class.to_owned())) {
+            Some(basis_points) => amount * U256::from(basis_points) / U256::from(MAX_TAX_BASIS_POINTS + basis_points),
+            None => U256::zero()
+        }
+    }
+
+    /*
+     * Share of the order tax in an amount paid or refunded for the order
+     */
+    pub(crate) fn order_tax_share(order: &Order, amount: Balance) -> Balance {
+        if order.tax == 0 || order.amount == 0 {
+            return 0;
+        }
+        (U256::from(order.tax) * U256::from(amount) / U256::from(order.amount)).as_u128()
+    }
+
+    pub(crate) fn internal_credit_tax(&mut self, order: &Order, tax: Balance) {
+        if let (Some(jurisdiction), true) = (&order.tax_jurisdiction, tax > 0) {
+            let key = (jurisdiction.clone(), order.token_id.clone());
+            self.tax_balances.insert(&key, &(self.tax_balances.get(&key).unwrap_or(0) + tax));
+        }
+    }
+
+    pub(crate) fn can_debit_tax(&self, order: &Order, tax: Balance) -> bool {
+        match &order.tax_jurisdiction {
+            Some(jurisdiction) => self.tax_balances.get(&(jurisdiction.clone(), order.token_id.clone())).unwrap_or(0) >= tax,
+            None => true
+        }
+    }
+
+    pub(crate) fn internal_debit_tax(&mut self, order: &Order, tax: Balance) {
+        if let (Some(jurisdiction), true) = (&order.tax_jurisdiction, tax > 0) {
+            let key = (jurisdiction.clone(), order.token_id.clone());
+            let balance = self.tax_balances.get(&key).unwrap_or(0);
+            assert!(balance >= tax, "ERROR_TAX_BALANCE_NOT_ENOUGH");
+            self.tax_balances.insert(&key, &(balance - tax));
+        }
+    }
+
+    pub(crate) fn internal_withdraw_tax(&mut self, jurisdiction: String, token: Option<AccountId>, receiver_id: AccountId, amount: U128) -> PromiseOrValue<U128> {
+        let key = (jurisdiction.clone(), token.clone());
+        let balance = self.tax_balances.get(&key).unwrap_or(0);
+        assert!(balance >= amount.0, "ERROR_TAX_BALANCE_NOT_ENOUGH");
+        self.tax_balances.insert(&key, &(balance - amount.0));
+
+        let promise = self.internal_transfer(token.clone(), receiver_id, amount.0, "Tax from payment contract")
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_attached_deposit(0)
+                    .with_static_gas(TRANSFER_GAS)
+                    .on_tax_withdraw(jurisdiction, token, amount)
+            );
+        PromiseOrValue::Promise(promise)
+    }
+
+    pub(crate) fn internal_on_tax_withdraw(&mut self, jurisdiction: String, token: Option<AccountId>, amount: U128) -> U128 {
+        assert_eq!(env::promise_results_count(), 1, "ERROR_TOO_MANY_RESULTS");
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_value) => U128(0),
+            PromiseResult::Failed => {
+                let key = (jurisdiction.clone(), token);
+                self.tax_balances.insert(&key, &(self.tax_balances.get(&key).unwrap_or(0) + amount.0));
+                log!("Failed to withdraw the tax of {}", jurisdiction);
+                amount
+            }
+        }
+    }
+}
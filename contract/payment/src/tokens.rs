@@ -0,0 +1,148 @@
+use near_sdk::{AccountId, Balance, Promise, PromiseResult, Timestamp, env, ext_contract, log, near_bindgen, serde_json};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub const NEAR_DECIMALS: u8 = 24;
+/// Decimals of normalized amounts, converted to each token's own decimals at payment time.
+pub const NORMALIZED_DECIMALS: u8 = 18;
+pub const FT_METADATA_GAS: Gas = Gas(5_000_000_000_000);
+
+/**
+ * Subset of the NEP-148 metadata returned by ft_metadata, other fields are ignored
+ */
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FungibleTokenMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8
+}
+
+#[ext_contract(ext_ft_metadata)]
+pub trait FungibleTokenMetadataProvider {
+    fn ft_metadata(&self) -> FungibleTokenMetadata;
+}
+
+/**
+ * Accepted FT with its cached metadata, decimals is None until ft_metadata resolved
+ */
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenInfo {
+    pub token_id: AccountId,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    pub updated_at: Option<Timestamp>
+}
+
+#[near_bindgen]
+impl EcommerceContract {
+    /*
+     * Accept a new FT for payments and cache its metadata
+     */
+    pub fn add_accepted_token(&mut self, token_id: AccountId) -> Promise {
+        self.assert_owner();
+        assert!(self.accepted_tokens.get(&token_id).is_none(), "ERROR_TOKEN_ALREADY_ACCEPTED");
+        self.internal_add_accepted_token(token_id)
+    }
+
+    pub fn remove_accepted_token(&mut self, token_id: AccountId) {
+        self.assert_owner();
+        assert_ne!(token_id, self.ft_contract_id, "ERROR_CANNOT_REMOVE_DEFAULT_TOKEN");
+        self.accepted_tokens.remove(&token_id).expect("ERROR_TOKEN_NOT_ACCEPTED");
+    }
+
+    pub fn refresh_token_metadata(&mut self, token_id: AccountId) -> Promise {
+        self.assert_owner();
+        self.get_accepted_token(token_id.clone());
+        self.internal_fetch_metadata(token_id)
+    }
+
+    pub fn get_accepted_token(&self, token_id: AccountId) -> TokenInfo {
+        self.accepted_tokens.get(&token_id).expect("ERROR_TOKEN_NOT_ACCEPTED")
+    }
+
+    pub fn get_accepted_tokens(&self) -> Vec<TokenInfo> {
+        self.accepted_tokens.values().collect()
+    }
+}
+
+impl EcommerceContract {
+    pub(crate) fn internal_add_accepted_token(&mut self, token_id: AccountId) -> Promise {
+        self.accepted_tokens.insert(&token_id, &TokenInfo {
+            token_id: token_id.clone(),
+            symbol: None,
+            decimals: None,
+            updated_at: None
+        });
+        self.internal_fetch_metadata(token_id)
+    }
+
+    fn internal_fetch_metadata(&self, token_id: AccountId) -> Promise {
+        ext_ft_metadata::ext(token_id.clone())
+            .with_static_gas(FT_METADATA_GAS)
+            .ft_metadata()
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_static_gas(FT_METADATA_GAS)
+                    .on_ft_metadata(token_id)
+            )
+    }
+
+    pub(crate) fn internal_on_ft_metadata(&mut self, token_id: AccountId) {
+        assert_eq!(env::promise_results_count(), 1, "ERROR_TOO_MANY_RESULTS");
+        // The token may have been removed while ft_metadata was in flight
+        let mut token = match self.accepted_tokens.get(&token_id) {
+            Some(token) => token,
+            None => return
+        };
+
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => {
+                let metadata: FungibleTokenMetadata = serde_json::from_slice(&value).expect("ERROR_NOT_VALID_METADATA");
+                token.symbol = Some(metadata.symbol);
+                token.decimals = Some(metadata.decimals);
+                token.updated_at = Some(env::block_timestamp());
+                self.accepted_tokens.insert(&token_id, &token);
+            },
+            PromiseResult::Failed => {
+                log!("Failed to fetch ft_metadata of {}", token_id);
+            }
+        }
+    }
+
+    /*
+     * token = None means NEAR, otherwise the FT must be accepted
+     */
+    pub(crate) fn payment_method_of(&self, token: &Option<AccountId>) -> PaymentMethod {
+        match token {
+            None => PaymentMethod::Near,
+            Some(token_id) => {
+                assert!(self.accepted_tokens.get(token_id).is_some(), "ERROR_TOKEN_NOT_ACCEPTED");
+                PaymentMethod::FungibleToken
+            }
+        }
+    }
+
+    pub(crate) fn token_decimals(&self, token: &Option<AccountId>) -> u8 {
+        match token {
+            None => NEAR_DECIMALS,
+            Some(token_id) => self.get_accepted_token(token_id.clone())
+                .decimals
+                .expect("ERROR_TOKEN_METADATA_NOT_FOUND")
+        }
+    }
+}
+
+/// Token amount (smallest unit) for a normalized amount, rounded up.
+pub(crate) fn normalized_to_amount(normalized_amount: Balance, decimals: u8) -> Balance {
+    let numerator = U256::from(normalized_amount) * U256::exp10(decimals as usize);
+    let denominator = U256::exp10(NORMALIZED_DECIMALS as usize);
+    let amount = (numerator + denominator - 1) / denominator;
+    assert!(amount <= U256::from(u128::MAX), "ERROR_AMOUNT_OVERFLOW");
+    amount.as_u128()
+}